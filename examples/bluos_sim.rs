@@ -0,0 +1,21 @@
+use bluos_api_rs::{SimModel, SimulatedPlayer};
+use std::net::SocketAddr;
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    // Serve a simulated player on the regular BluOS port, pass another address to override
+    let addr: SocketAddr = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| "127.0.0.1:11000".to_string())
+        .parse()?;
+
+    let player = SimulatedPlayer::bind(addr, SimModel::default()).await?;
+    println!(
+        "Simulated BluOS player listening on http://{}",
+        player.addr()
+    );
+
+    // Serve until the process is killed
+    futures::future::pending::<()>().await;
+    Ok(())
+}
@@ -1,20 +1,23 @@
-#[cfg(not(feature = "discover"))]
+#[cfg(not(any(feature = "discover", feature = "discover-mdns-sd")))]
 fn main() {
     println!("This example needs the discover feature to be enabled");
 }
 
-#[cfg(feature = "discover")]
+#[cfg(any(feature = "discover", feature = "discover-mdns-sd"))]
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     use anyhow::Context;
     use bluos_api_rs::{BluOS, Discovery};
+    use std::time::Duration;
 
     // Doing it manually
-    //let bluos = BluOS::new(Ipv4Addr::from_str("10.0.1.36")?, None).await?;
+    //let bluos = BluOS::with_hostname("10.0.1.36", None)?;
 
     // Manually is boring, let's use mDNS to discover this!
     // Find the first device in our network
-    let device = Discovery::discover_one().await.context("discover_one")?;
+    let device = Discovery::discover_one(Duration::from_secs(10))
+        .await
+        .context("discover_one")?;
 
     // Create a new BluOS device from the discovered address
     let bluos = BluOS::new_from_discovered(device).context("new_from_discovered")?;
@@ -0,0 +1,263 @@
+//! A synchronous BluOS client
+//!
+//! The client runs the async [BluOS](crate::BluOS) on a runtime of its own, so there is no
+//! runtime to manage. Like `reqwest::blocking` it must not be used from within an async
+//! runtime, use the async client there.
+use crate::device::IdResponse;
+use crate::error::Error;
+use crate::{
+    Browse, BrowseItem, DeviceInfo, Pagination, PlayerAddress, Playlist, Presets, RawCommand,
+    RepeatSetting, State, Status, StatusEvent, SyncSlave, SyncStatus, VolumeResponse,
+};
+use futures::stream::{Stream, StreamExt};
+use serde::de::DeserializeOwned;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::pin::Pin;
+use tokio::runtime::Runtime;
+
+#[cfg(any(feature = "discover", feature = "discover-mdns-sd", feature = "lsdp"))]
+use crate::DiscoveredBluOSDevice;
+
+/// Synchronous counterpart of [crate::BluOS], see there for the documentation of each method
+#[derive(Debug)]
+pub struct BluOS {
+    inner: crate::BluOS,
+    runtime: Runtime,
+}
+
+impl BluOS {
+    pub fn new(addr: Ipv4Addr, custom_port: Option<u16>) -> Result<BluOS, Error> {
+        BluOS::from_async(crate::BluOS::new(addr, custom_port)?)
+    }
+
+    pub fn with_ipv6(addr: Ipv6Addr, custom_port: Option<u16>) -> Result<BluOS, Error> {
+        BluOS::from_async(crate::BluOS::with_ipv6(addr, custom_port)?)
+    }
+
+    pub fn with_socket_addr(addr: SocketAddr) -> Result<BluOS, Error> {
+        BluOS::from_async(crate::BluOS::with_socket_addr(addr)?)
+    }
+
+    pub fn with_hostname(hostname: &str, custom_port: Option<u16>) -> Result<BluOS, Error> {
+        BluOS::from_async(crate::BluOS::with_hostname(hostname, custom_port)?)
+    }
+
+    pub fn with_url(base_url: &str) -> Result<BluOS, Error> {
+        BluOS::from_async(crate::BluOS::with_url(base_url)?)
+    }
+
+    #[cfg(any(feature = "discover", feature = "discover-mdns-sd", feature = "lsdp"))]
+    pub fn new_from_discovered(d: DiscoveredBluOSDevice) -> Result<BluOS, Error> {
+        BluOS::from_async(crate::BluOS::new_from_discovered(d)?)
+    }
+
+    /// Wrap an async client, for example one created with [crate::BluOS::builder]
+    pub fn from_async(inner: crate::BluOS) -> Result<BluOS, Error> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+
+        Ok(BluOS { inner, runtime })
+    }
+
+    pub fn send_raw(&self, cmd: RawCommand) -> Result<String, Error> {
+        self.runtime.block_on(self.inner.send_raw(cmd))
+    }
+
+    pub fn send_raw_xml<T: DeserializeOwned>(&self, cmd: RawCommand) -> Result<T, Error> {
+        self.runtime.block_on(self.inner.send_raw_xml(cmd))
+    }
+
+    pub fn status(&self) -> Result<Status, Error> {
+        self.runtime.block_on(self.inner.status())
+    }
+
+    pub fn sync_status(&self) -> Result<SyncStatus, Error> {
+        self.runtime.block_on(self.inner.sync_status())
+    }
+
+    pub fn device_info(&self) -> Result<DeviceInfo, Error> {
+        self.runtime.block_on(self.inner.device_info())
+    }
+
+    /// Iterate over the status of the BluOS device as it changes, each call to next
+    /// blocks until the status changes
+    pub fn status_stream(&self) -> Iter<'_, Status> {
+        self.iter(self.inner.status_stream())
+    }
+
+    /// Iterate over the changes of the BluOS device, each call to next blocks until
+    /// something changes
+    pub fn status_events(&self) -> Iter<'_, StatusEvent> {
+        self.iter(self.inner.status_events())
+    }
+
+    fn iter<'a, T>(&'a self, stream: impl Stream<Item = Result<T, Error>> + 'a) -> Iter<'a, T> {
+        Iter {
+            runtime: &self.runtime,
+            stream: Box::pin(stream),
+        }
+    }
+
+    pub fn browse(&self, key: Option<&str>) -> Result<Browse, Error> {
+        self.runtime.block_on(self.inner.browse(key))
+    }
+
+    pub fn update_library(&self) -> Result<(), Error> {
+        self.runtime.block_on(self.inner.update_library())
+    }
+
+    pub fn play(&self) -> Result<State, Error> {
+        self.runtime.block_on(self.inner.play())
+    }
+
+    pub fn play_with_options(
+        &self,
+        seek: Option<i64>,
+        input_type: Option<String>,
+        index: Option<i64>,
+    ) -> Result<State, Error> {
+        self.runtime
+            .block_on(self.inner.play_with_options(seek, input_type, index))
+    }
+
+    pub fn play_url(&self, url: &str) -> Result<State, Error> {
+        self.runtime.block_on(self.inner.play_url(url))
+    }
+
+    pub fn play_item(&self, item: &BrowseItem) -> Result<State, Error> {
+        self.runtime.block_on(self.inner.play_item(item))
+    }
+
+    pub fn pause(&self, toggle: bool) -> Result<State, Error> {
+        self.runtime.block_on(self.inner.pause(toggle))
+    }
+
+    pub fn stop(&self) -> Result<State, Error> {
+        self.runtime.block_on(self.inner.stop())
+    }
+
+    pub fn skip(&self) -> Result<IdResponse, Error> {
+        self.runtime.block_on(self.inner.skip())
+    }
+
+    pub fn back(&self) -> Result<IdResponse, Error> {
+        self.runtime.block_on(self.inner.back())
+    }
+
+    pub fn shuffle(&self, enable: bool) -> Result<(), Error> {
+        self.runtime.block_on(self.inner.shuffle(enable))
+    }
+
+    pub fn repeat(&self, setting: RepeatSetting) -> Result<(), Error> {
+        self.runtime.block_on(self.inner.repeat(setting))
+    }
+
+    pub fn volume(&self) -> Result<VolumeResponse, Error> {
+        self.runtime.block_on(self.inner.volume())
+    }
+
+    pub fn set_volume(
+        &self,
+        level: u8,
+        tell_slaves: Option<bool>,
+    ) -> Result<VolumeResponse, Error> {
+        self.runtime
+            .block_on(self.inner.set_volume(level, tell_slaves))
+    }
+
+    pub fn set_volume_db(
+        &self,
+        db: f64,
+        tell_slaves: Option<bool>,
+    ) -> Result<VolumeResponse, Error> {
+        self.runtime
+            .block_on(self.inner.set_volume_db(db, tell_slaves))
+    }
+
+    pub fn step_volume_db(
+        &self,
+        db: f64,
+        tell_slaves: Option<bool>,
+    ) -> Result<VolumeResponse, Error> {
+        self.runtime
+            .block_on(self.inner.step_volume_db(db, tell_slaves))
+    }
+
+    pub fn mute(&self, tell_slaves: Option<bool>) -> Result<VolumeResponse, Error> {
+        self.runtime.block_on(self.inner.mute(tell_slaves))
+    }
+
+    pub fn unmute(&self, tell_slaves: Option<bool>) -> Result<VolumeResponse, Error> {
+        self.runtime.block_on(self.inner.unmute(tell_slaves))
+    }
+
+    pub fn presets(&self) -> Result<Presets, Error> {
+        self.runtime.block_on(self.inner.presets())
+    }
+
+    pub fn load_preset(&self, id: i64) -> Result<(), Error> {
+        self.runtime.block_on(self.inner.load_preset(id))
+    }
+
+    pub fn next_preset(&self) -> Result<(), Error> {
+        self.runtime.block_on(self.inner.next_preset())
+    }
+
+    pub fn previous_preset(&self) -> Result<(), Error> {
+        self.runtime.block_on(self.inner.previous_preset())
+    }
+
+    pub fn add_slave(
+        &self,
+        slave: impl Into<PlayerAddress>,
+        group: Option<&str>,
+    ) -> Result<Vec<SyncSlave>, Error> {
+        self.runtime.block_on(self.inner.add_slave(slave, group))
+    }
+
+    pub fn add_slaves<P: Into<PlayerAddress>>(
+        &self,
+        slaves: impl IntoIterator<Item = P>,
+        group: Option<&str>,
+    ) -> Result<Vec<SyncSlave>, Error> {
+        self.runtime.block_on(self.inner.add_slaves(slaves, group))
+    }
+
+    pub fn remove_slave(&self, slave: impl Into<PlayerAddress>) -> Result<SyncStatus, Error> {
+        self.runtime.block_on(self.inner.remove_slave(slave))
+    }
+
+    pub fn queue(&self, pagination: Option<Pagination>) -> Result<Playlist, Error> {
+        self.runtime.block_on(self.inner.queue(pagination))
+    }
+
+    pub fn queue_delete_song(&self, position: u64) -> Result<(), Error> {
+        self.runtime
+            .block_on(self.inner.queue_delete_song(position))
+    }
+
+    pub fn queue_clear(&self) -> Result<(), Error> {
+        self.runtime.block_on(self.inner.queue_clear())
+    }
+}
+
+impl From<&BluOS> for PlayerAddress {
+    fn from(b: &BluOS) -> Self {
+        (&b.inner).into()
+    }
+}
+
+/// Blocking iterator over a stream of the async client
+pub struct Iter<'a, T> {
+    runtime: &'a Runtime,
+    stream: Pin<Box<dyn Stream<Item = Result<T, Error>> + 'a>>,
+}
+
+impl<T> Iterator for Iter<'_, T> {
+    type Item = Result<T, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.runtime.block_on(self.stream.next())
+    }
+}
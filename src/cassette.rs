@@ -0,0 +1,238 @@
+#![cfg(feature = "cassette")]
+use crate::error::Error;
+use crate::{Transport, TransportRequest, TransportResponse};
+use futures::future::BoxFuture;
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+
+// Cassette format
+// Every exchange is a request line, a response line with the status and the length of the
+// body in bytes, then the body itself and a newline:
+//
+//   > /Volume?level=20
+//   < 200 57
+//   <?xml version="1.0" encoding="UTF-8"?><volume>20</volume>
+//
+// The length keeps bodies intact whatever they contain, and the files stay readable in a diff
+
+/// A [Transport] that records the answers of a real player to a file, and replays them
+/// later so tests run without the player
+///
+/// Requests are matched by path and query, the host is ignored so a recording can be replayed
+/// with any address. A request that was sent several times gets the recorded responses in the
+/// order they were recorded
+///
+/// ```no_run
+/// # use bluos_api_rs::{BluOS, CassetteTransport, Transport};
+/// # async fn run(http: impl Transport + 'static) -> Result<(), bluos_api_rs::Error> {
+/// // Once, with the player on the network, through a transport such as ReqwestTransport
+/// let transport = CassetteTransport::record("node2i.cassette", http)?;
+/// let bluos = BluOS::builder().hostname("192.168.1.100").transport(transport).build()?;
+/// bluos.status().await?;
+///
+/// // Any time after that
+/// let transport = CassetteTransport::replay("node2i.cassette")?;
+/// let bluos = BluOS::builder().hostname("node2i").transport(transport).build()?;
+/// bluos.status().await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct CassetteTransport {
+    mode: Mode,
+}
+
+#[derive(Debug)]
+enum Mode {
+    Record {
+        inner: Box<dyn Transport>,
+        file: Mutex<File>,
+    },
+    Replay {
+        responses: Mutex<HashMap<String, VecDeque<TransportResponse>>>,
+    },
+}
+
+impl CassetteTransport {
+    /// Send requests with the inner transport and write every response to the file,
+    /// replacing what was recorded there before
+    pub fn record(
+        path: impl AsRef<Path>,
+        inner: impl Transport + 'static,
+    ) -> Result<CassetteTransport, Error> {
+        Ok(CassetteTransport {
+            mode: Mode::Record {
+                inner: Box::new(inner),
+                file: Mutex::new(File::create(path)?),
+            },
+        })
+    }
+
+    /// Answer requests with the responses recorded in the file
+    pub fn replay(path: impl AsRef<Path>) -> Result<CassetteTransport, Error> {
+        CassetteTransport::from_recording(&std::fs::read_to_string(path)?)
+    }
+
+    /// Answer requests with the responses of a recording, e.g. one included with `include_str!`
+    pub fn from_recording(recording: &str) -> Result<CassetteTransport, Error> {
+        let mut responses: HashMap<String, VecDeque<TransportResponse>> = HashMap::new();
+        for (path_and_query, response) in decode(recording)? {
+            responses
+                .entry(path_and_query)
+                .or_default()
+                .push_back(response);
+        }
+
+        Ok(CassetteTransport {
+            mode: Mode::Replay {
+                responses: Mutex::new(responses),
+            },
+        })
+    }
+}
+
+impl Transport for CassetteTransport {
+    fn get(&self, request: TransportRequest) -> BoxFuture<'_, Result<TransportResponse, Error>> {
+        match &self.mode {
+            Mode::Record { inner, file } => Box::pin(async move {
+                let path_and_query = request.path_and_query.clone();
+                let response = inner.get(request).await?;
+                file.lock()
+                    .unwrap()
+                    .write_all(encode(&path_and_query, &response).as_bytes())?;
+
+                Ok(response)
+            }),
+            Mode::Replay { responses } => {
+                let response = responses
+                    .lock()
+                    .unwrap()
+                    .get_mut(&request.path_and_query)
+                    .and_then(VecDeque::pop_front);
+
+                Box::pin(async move {
+                    response.ok_or_else(|| {
+                        Error::CassetteError(format!(
+                            "no recorded response left for {}",
+                            request.path_and_query
+                        ))
+                    })
+                })
+            }
+        }
+    }
+}
+
+fn encode(path_and_query: &str, response: &TransportResponse) -> String {
+    format!(
+        "> {}\n< {} {}\n{}\n",
+        path_and_query,
+        response.status,
+        response.body.len(),
+        response.body
+    )
+}
+
+fn decode(recording: &str) -> Result<Vec<(String, TransportResponse)>, Error> {
+    let invalid = |reason: &str| Error::CassetteError(format!("invalid recording, {}", reason));
+
+    let mut exchanges = Vec::new();
+    let mut rest = recording.trim_start_matches('\n');
+    while !rest.is_empty() {
+        let (request, r) = rest
+            .split_once('\n')
+            .ok_or_else(|| invalid("the response line is missing"))?;
+        let path_and_query = request
+            .strip_prefix("> ")
+            .ok_or_else(|| invalid("expected a request line"))?;
+
+        let (response, r) = r
+            .split_once('\n')
+            .ok_or_else(|| invalid("the body is missing"))?;
+        let (status, length) = response
+            .strip_prefix("< ")
+            .and_then(|r| r.split_once(' '))
+            .ok_or_else(|| invalid("expected a response line"))?;
+        let status = status
+            .parse()
+            .map_err(|_| invalid("the status is not a number"))?;
+        let length: usize = length
+            .parse()
+            .map_err(|_| invalid("the length is not a number"))?;
+
+        let body = r
+            .get(..length)
+            .ok_or_else(|| invalid("the body is shorter than its length"))?;
+        rest = r[length..]
+            .strip_prefix('\n')
+            .ok_or_else(|| invalid("the body is longer than its length"))?
+            .trim_start_matches('\n');
+
+        exchanges.push((
+            path_and_query.to_string(),
+            TransportResponse {
+                status,
+                body: body.to_string(),
+            },
+        ));
+    }
+
+    Ok(exchanges)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode, encode};
+    use crate::error::Error;
+    use crate::TransportResponse;
+
+    #[test]
+    fn round_trip() {
+        let exchanges = vec![
+            (
+                "/Status".to_string(),
+                TransportResponse {
+                    status: 200,
+                    body: "<status>\n> /Status\n< 200 1\n</status>".to_string(),
+                },
+            ),
+            (
+                "/Browse?key=Tidal%3A".to_string(),
+                TransportResponse {
+                    status: 503,
+                    body: String::new(),
+                },
+            ),
+            (
+                "/Status".to_string(),
+                TransportResponse {
+                    status: 200,
+                    body: "<status><artist>Sigur Rós</artist></status>".to_string(),
+                },
+            ),
+        ];
+
+        let recording: String = exchanges.iter().map(|(p, r)| encode(p, r)).collect();
+        assert_eq!(decode(&recording).unwrap(), exchanges);
+    }
+
+    #[test]
+    fn invalid_recordings() {
+        for recording in [
+            "/Status\n< 200 0\n\n",
+            "> /Status\n200 0\n\n",
+            "> /Status\n< 200 10\n<status/>\n",
+            "> /Status\n< 200 2\n<status/>\n",
+            "> /Status\n< OK 0\n\n",
+        ] {
+            assert!(
+                matches!(decode(recording), Err(Error::CassetteError(_))),
+                "{:?}",
+                recording
+            );
+        }
+    }
+}
@@ -1,15 +1,37 @@
+mod builder;
 mod command;
+mod events;
+mod group;
 mod responses;
+mod retry;
+mod transport;
 
 use crate::error::Error;
+pub use builder::BluOSBuilder;
 use command::Command;
-use reqwest::Response;
-use responses::StateResponse;
-pub use responses::{Browse, IdResponse, Playlist, PlaylistEntry, State, Status};
+pub use command::RawCommand;
+pub use events::StatusEvent;
+use futures::future;
+use futures::stream::{self, Stream, StreamExt};
+use futures_timer::Delay;
+pub use group::{GroupTopology, PlayerAddress, PlayerGroup};
+use responses::{device_error_message, AddSlaveResponse, StateResponse};
+pub use responses::{
+    Battery, Browse, BrowseItem, DeviceInfo, IdResponse, Playlist, PlaylistEntry, Preset, Presets,
+    State, Status, SyncMaster, SyncSlave, SyncStatus, VolumeResponse,
+};
+use retry::is_transient;
+pub use retry::RetryPolicy;
+use serde::de::DeserializeOwned;
 use serde::Deserialize;
-use std::net::{Ipv4Addr, SocketAddr};
-
-#[cfg(feature = "discover")]
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+#[cfg(feature = "reqwest")]
+pub use transport::ReqwestTransport;
+pub use transport::{Transport, TransportRequest, TransportResponse};
+
+#[cfg(any(feature = "discover", feature = "discover-mdns-sd", feature = "lsdp"))]
 use crate::DiscoveredBluOSDevice;
 
 // Documented here
@@ -19,7 +41,15 @@ pub struct BluOS {
     hostname: String,
     port: u16,
 
-    client: reqwest::Client,
+    transport: Arc<dyn Transport>,
+    timeout: Duration,
+    long_poll_timeout: Duration,
+    user_agent: Option<String>,
+    /// Used by the volume methods if they are not told whether to change the whole group
+    tell_slaves: Option<bool>,
+    retry: RetryPolicy,
+    /// Presets are cached until Status.prid tells us they changed
+    presets: Mutex<Option<Presets>>,
 }
 
 impl BluOS {
@@ -27,61 +57,243 @@ impl BluOS {
     ///
     /// - If you for some reason managed to make your BluOS device listen on another port, define it using custom_port
     pub fn new(addr: Ipv4Addr, custom_port: Option<u16>) -> Result<BluOS, Error> {
-        let port = match custom_port {
-            Some(v) => v,
-            None => 11000,
-        };
+        BluOS::builder()
+            .ip(addr.into())
+            .port(custom_port.unwrap_or(DEFAULT_PORT))
+            .build()
+    }
 
-        Ok(BluOS {
-            hostname: addr.to_string(),
-            port,
-            client: reqwest::Client::new(),
-        })
+    /// Create a new BluOS device from an Ipv6Addr, it is bracketed in the request URLs
+    pub fn with_ipv6(addr: Ipv6Addr, custom_port: Option<u16>) -> Result<BluOS, Error> {
+        BluOS::builder()
+            .ip(addr.into())
+            .port(custom_port.unwrap_or(DEFAULT_PORT))
+            .build()
     }
 
     pub fn with_socket_addr(addr: SocketAddr) -> Result<BluOS, Error> {
-        Ok(BluOS {
-            hostname: addr.ip().to_string(),
-            port: addr.port(),
-            client: reqwest::Client::new(),
-        })
+        BluOS::builder().socket_addr(addr).build()
+    }
+
+    /// Create a new BluOS device from a hostname such as `kitchen.local`, or an IP address
+    ///
+    /// The hostname is validated but not resolved, that happens when a request is sent
+    pub fn with_hostname(hostname: &str, custom_port: Option<u16>) -> Result<BluOS, Error> {
+        BluOS::builder()
+            .hostname(hostname)
+            .port(custom_port.unwrap_or(DEFAULT_PORT))
+            .build()
+    }
+
+    /// Create a new BluOS device from a base URL such as `http://192.168.1.100:11000`
+    ///
+    /// Only plain http URLs without a path are accepted. Without a port the BluOS port 11000 is used
+    pub fn with_url(base_url: &str) -> Result<BluOS, Error> {
+        BluOS::builder().url(base_url).build()
     }
 
     /// Create a new BluOS device from a discovered device
-    #[cfg(feature = "discover")]
+    #[cfg(any(feature = "discover", feature = "discover-mdns-sd", feature = "lsdp"))]
     pub fn new_from_discovered(d: DiscoveredBluOSDevice) -> Result<BluOS, Error> {
-        Ok(BluOS {
-            hostname: d.hostname,
-            port: d.port,
-            client: reqwest::Client::new(),
-        })
+        BluOS::builder().hostname(&d.hostname).port(d.port).build()
+    }
+
+    /// Configure the client, for example to set timeouts or use your own transport
+    pub fn builder() -> BluOSBuilder {
+        BluOSBuilder::new()
     }
 
     fn cmd(&self, action: &str) -> Command {
         Command::new(&self.hostname, self.port, action)
     }
 
-    /// Send your own command to the BluOS Device
-    async fn command(&self, cmd: Command) -> Result<Response, Error> {
-        Ok(self.client.get(cmd.build()).send().await?)
+    /// A command that only reads from the player, so it can be retried
+    fn read_cmd(&self, action: &str) -> Command {
+        let mut cmd = self.cmd(action);
+        cmd.set_idempotent();
+        cmd
+    }
+
+    /// Send a command to the BluOS Device
+    ///
+    /// Idempotent commands are retried according to the retry policy.
+    /// Returns the body of the response
+    async fn command(&self, cmd: Command) -> Result<String, Error> {
+        let attempts = match cmd.is_idempotent() {
+            true => self.retry.max_attempts.max(1),
+            false => 1,
+        };
+
+        let mut attempt = 1;
+        loop {
+            match self.send(&cmd).await {
+                Err(e) if attempt < attempts && is_transient(&e) => {
+                    let delay = self.retry.backoff(attempt);
+                    log::warn!(
+                        "{} to {}:{} failed, retrying in {:?} (attempt {}/{}): {}",
+                        cmd.name(),
+                        self.hostname,
+                        self.port,
+                        delay,
+                        attempt + 1,
+                        attempts,
+                        e
+                    );
+                    Delay::new(delay).await;
+                    attempt += 1;
+                }
+                res => return res,
+            }
+        }
     }
 
-    /// Send your own command to the BluOS device and expect a response
+    async fn send(&self, cmd: &Command) -> Result<String, Error> {
+        let request = TransportRequest {
+            host: self.hostname.clone(),
+            port: self.port,
+            path_and_query: cmd.path_and_query()?,
+            timeout: cmd.timeout().unwrap_or(self.timeout),
+            user_agent: self.user_agent.clone(),
+        };
+        let TransportResponse { status, body } = self.transport.get(request).await?;
+
+        // The device reports rejected requests as an <error> document. Busy players send one
+        // along with a 503 too, those errors are still retried
+        if let Some(message) = device_error_message(&body) {
+            return Err(Error::DeviceError {
+                endpoint: cmd.name().to_string(),
+                status,
+                message,
+                body,
+            });
+        }
+
+        if !(200..300).contains(&status) {
+            return Err(Error::HttpStatusError {
+                endpoint: cmd.name().to_string(),
+                status,
+                body,
+            });
+        }
+
+        Ok(body)
+    }
+
+    /// Send a command to the BluOS device and expect a response
     /// The function is generic and uses the type to determine what struct to deserialize to
     async fn command_response<'a, T: Deserialize<'a>>(&self, cmd: Command) -> Result<T, Error> {
-        let t = self.client.get(cmd.build()).send().await?.text().await?;
+        let t = self.command(cmd).await?;
         Ok(serde_xml_rs::from_str(&t)?)
     }
 
+    /// Send your own command to the BluOS device, for endpoints without a method
+    ///
+    /// Error documents and HTTP errors are reported like for any other command.
+    /// Returns the body of the response
+    pub async fn send_raw(&self, cmd: RawCommand) -> Result<String, Error> {
+        self.command(cmd.into_command(&self.hostname, self.port))
+            .await
+    }
+
+    /// Send your own command to the BluOS device and deserialize the XML response
+    /// into your own type, or one of the response types of this crate
+    pub async fn send_raw_xml<T: DeserializeOwned>(&self, cmd: RawCommand) -> Result<T, Error> {
+        self.command_response(cmd.into_command(&self.hostname, self.port))
+            .await
+    }
+
     /// Get the current status of the BluOS device
     pub async fn status(&self) -> Result<Status, Error> {
-        let status: Status = self.command_response(self.cmd("Status")).await?;
+        let status: Status = self.command_response(self.read_cmd("Status")).await?;
+        self.observe_prid(status.prid);
 
         Ok(status)
     }
 
+    /// Get the sync status of the BluOS device
+    ///
+    /// This contains the identity of the player such as name, model and MAC address
+    /// as well as the group it belongs to.
+    pub async fn sync_status(&self) -> Result<SyncStatus, Error> {
+        let sync_status: SyncStatus = self.command_response(self.read_cmd("SyncStatus")).await?;
+
+        Ok(sync_status)
+    }
+
+    /// Get a summary of the identity of the BluOS device
+    ///
+    /// The MAC address is the stable identity of a player, the hostname is not.
+    pub async fn device_info(&self) -> Result<DeviceInfo, Error> {
+        Ok(self.sync_status().await?.into())
+    }
+
+    /// Stream the status of the BluOS device as it changes
+    ///
+    /// The first item is the current status, after that the stream uses the long-polling
+    /// `timeout` and `etag` parameters of /Status so it only yields when the player state
+    /// actually changes. Errors are yielded as they happen and the stream then reconnects
+    /// with an exponential backoff.
+    ///
+    /// The returned stream has to be pinned before polling, for example with `futures::pin_mut!`
+    pub fn status_stream(&self) -> impl Stream<Item = Result<Status, Error>> + '_ {
+        stream::unfold(StatusPoll::default(), move |mut poll| async move {
+            loop {
+                if poll.failures > 0 {
+                    Delay::new(status_backoff(poll.failures)).await;
+                }
+
+                let mut cmd = self.read_cmd("Status");
+                if let Some(etag) = &poll.etag {
+                    cmd.add_param("timeout", self.long_poll_timeout.as_secs());
+                    cmd.add_param("etag", etag);
+                    cmd.set_timeout(self.long_poll_timeout + self.timeout);
+                }
+
+                match self.command_response::<Status>(cmd).await {
+                    Ok(status) => {
+                        self.observe_prid(status.prid);
+                        poll.failures = 0;
+                        // The long poll timed out without any change
+                        if poll.etag.as_ref() == Some(&status.etag) {
+                            continue;
+                        }
+                        poll.etag = Some(status.etag.clone());
+                        return Some((Ok(status), poll));
+                    }
+                    Err(e) => {
+                        poll.failures = poll.failures.saturating_add(1);
+                        return Some((Err(e), poll));
+                    }
+                }
+            }
+        })
+    }
+
+    /// Stream the changes of the BluOS device status as typed events
+    ///
+    /// This is built on top of status_stream, every new status is compared to the previous one
+    /// using StatusEvent::diff. No events are emitted for the first status.
+    pub fn status_events(&self) -> impl Stream<Item = Result<StatusEvent, Error>> + '_ {
+        self.status_stream()
+            .scan(None, |previous: &mut Option<Status>, item| {
+                let events = match item {
+                    Ok(status) => {
+                        let events = match previous.as_ref() {
+                            Some(p) => StatusEvent::diff(p, &status),
+                            None => Vec::new(),
+                        };
+                        *previous = Some(status);
+                        events.into_iter().map(Ok).collect()
+                    }
+                    Err(e) => vec![Err(e)],
+                };
+                future::ready(Some(stream::iter(events)))
+            })
+            .flatten()
+    }
+
     pub async fn browse(&self, key: Option<&str>) -> Result<Browse, Error> {
-        let mut cmd = self.cmd("Browse");
+        let mut cmd = self.read_cmd("Browse");
         cmd.add_optional("key", key);
         let browse: Browse = self.command_response(cmd).await?;
         Ok(browse)
@@ -103,15 +315,15 @@ impl BluOS {
 
     /// Plays whatever source is currently active
     pub async fn play(&self) -> Result<State, Error> {
-        Ok(self.play_with_options(None, None, None).await?)
+        self.play_with_options(None, None, None).await
     }
 
     /// Play with the ability to define options
     /// - seek: time to seek in the track, max is total_length from status of the track
     /// - input_type:  Selects an input before starting playback.
-    /// Possible values for inputType are: analog, spdif, hdmi or bluetooth.
+    ///   Possible values for inputType are: analog, spdif, hdmi or bluetooth.
     /// - index: For players with more than one input, this indicates which input of the specified
-    /// type to play. Used only with inputType parameter. Default value is 1.
+    ///   type to play. Used only with inputType parameter. Default value is 1.
     pub async fn play_with_options(
         &self,
         seek: Option<i64>,
@@ -127,6 +339,32 @@ impl BluOS {
         let state: StateResponse = self.command_response(cmd).await?;
         Ok(state.state)
     }
+
+    /// Play a stream directly from a URL
+    pub async fn play_url(&self, url: &str) -> Result<State, Error> {
+        let mut cmd = self.cmd("Play");
+        cmd.add_param("url", url);
+
+        let state: StateResponse = self.command_response(cmd).await?;
+        Ok(state.state)
+    }
+
+    /// Play an item found using browse
+    ///
+    /// Uses the playURL of the item, falling back to the autoplayURL.
+    /// Returns Error::NotPlayableError if the item has neither.
+    pub async fn play_item(&self, item: &BrowseItem) -> Result<State, Error> {
+        let path = item
+            .play_url
+            .as_deref()
+            .or(item.autoplay_url.as_deref())
+            .ok_or(Error::NotPlayableError)?;
+
+        let cmd = Command::from_path(&self.hostname, self.port, path);
+        let state: StateResponse = self.command_response(cmd).await?;
+        Ok(state.state)
+    }
+
     /// Pause playback
     /// - toggle: If set to 1, then the current pause state is toggled.
     pub async fn pause(&self, toggle: bool) -> Result<State, Error> {
@@ -161,11 +399,7 @@ impl BluOS {
     /// The original (not shuffled) queue is retained for restore when shuffle is disabled.
     pub async fn shuffle(&self, enable: bool) -> Result<(), Error> {
         let mut cmd = self.cmd("Shuffle");
-        if enable {
-            cmd.add_param("state", 1 as u8);
-        } else {
-            cmd.add_param("state", 0 as u8);
-        }
+        cmd.add_param("state", enable as u8);
         self.command(cmd).await?;
         Ok(())
     }
@@ -182,20 +416,205 @@ impl BluOS {
         Ok(())
     }
 
+    ///////////////////
+    // Volume
+    ///////////////////
+
+    /// Get the current volume of the BluOS device
+    pub async fn volume(&self) -> Result<VolumeResponse, Error> {
+        let volume: VolumeResponse = self.command_response(self.read_cmd("Volume")).await?;
+        Ok(volume)
+    }
+
+    /// Set the volume level in percentage, 0 to 100
+    ///
+    /// Levels above 100 are clamped to 100 before they are sent to the player.
+    /// - tell_slaves: If set, the volume change is applied to the whole group
+    pub async fn set_volume(
+        &self,
+        level: u8,
+        tell_slaves: Option<bool>,
+    ) -> Result<VolumeResponse, Error> {
+        let mut cmd = self.cmd("Volume");
+        cmd.add_param("level", level.min(100));
+        self.volume_command(cmd, tell_slaves).await
+    }
+
+    /// Set the volume level in decibel
+    /// - tell_slaves: If set, the volume change is applied to the whole group
+    pub async fn set_volume_db(
+        &self,
+        db: f64,
+        tell_slaves: Option<bool>,
+    ) -> Result<VolumeResponse, Error> {
+        let mut cmd = self.cmd("Volume");
+        cmd.add_param("abs_db", db);
+        self.volume_command(cmd, tell_slaves).await
+    }
+
+    /// Step the volume up or down by a relative amount of decibel
+    ///
+    /// Positive values turn the volume up, negative values turn it down.
+    /// - tell_slaves: If set, the volume change is applied to the whole group
+    pub async fn step_volume_db(
+        &self,
+        db: f64,
+        tell_slaves: Option<bool>,
+    ) -> Result<VolumeResponse, Error> {
+        let mut cmd = self.cmd("Volume");
+        cmd.add_param("db", db);
+        self.volume_command(cmd, tell_slaves).await
+    }
+
+    /// Mute the BluOS device
+    /// - tell_slaves: If set, the whole group is muted
+    pub async fn mute(&self, tell_slaves: Option<bool>) -> Result<VolumeResponse, Error> {
+        let mut cmd = self.cmd("Volume");
+        cmd.add_param("mute", 1);
+        self.volume_command(cmd, tell_slaves).await
+    }
+
+    /// Unmute the BluOS device, restoring the volume it had before it was muted
+    /// - tell_slaves: If set, the whole group is unmuted
+    pub async fn unmute(&self, tell_slaves: Option<bool>) -> Result<VolumeResponse, Error> {
+        let mut cmd = self.cmd("Volume");
+        cmd.add_param("mute", 0);
+        self.volume_command(cmd, tell_slaves).await
+    }
+
+    async fn volume_command(
+        &self,
+        mut cmd: Command,
+        tell_slaves: Option<bool>,
+    ) -> Result<VolumeResponse, Error> {
+        let tell_slaves = tell_slaves.or(self.tell_slaves);
+        cmd.add_optional("tell_slaves", tell_slaves.map(|t| t as u8));
+        let volume: VolumeResponse = self.command_response(cmd).await?;
+        Ok(volume)
+    }
+
+    ///////////////////
+    // Presets
+    ///////////////////
+
+    /// Get the presets of the BluOS device
+    ///
+    /// The list is cached and only fetched again once a Status response reports a new prid
+    pub async fn presets(&self) -> Result<Presets, Error> {
+        if let Some(presets) = self.presets.lock().unwrap().as_ref() {
+            return Ok(presets.clone());
+        }
+
+        let presets: Presets = self.command_response(self.read_cmd("Presets")).await?;
+        *self.presets.lock().unwrap() = Some(presets.clone());
+
+        Ok(presets)
+    }
+
+    /// Load the preset with the given id
+    pub async fn load_preset(&self, id: i64) -> Result<(), Error> {
+        let mut cmd = self.cmd("Preset");
+        cmd.add_param("id", id);
+
+        self.command(cmd).await?;
+        Ok(())
+    }
+
+    /// Load the next preset
+    pub async fn next_preset(&self) -> Result<(), Error> {
+        let mut cmd = self.cmd("Preset");
+        cmd.add_param("id", "+1");
+
+        self.command(cmd).await?;
+        Ok(())
+    }
+
+    /// Load the previous preset
+    pub async fn previous_preset(&self) -> Result<(), Error> {
+        let mut cmd = self.cmd("Preset");
+        cmd.add_param("id", "-1");
+
+        self.command(cmd).await?;
+        Ok(())
+    }
+
+    /// Drop the cached presets if the prid reported by the player no longer matches
+    fn observe_prid(&self, prid: u8) {
+        let mut presets = self.presets.lock().unwrap();
+        if presets.as_ref().is_some_and(|p| p.prid != prid) {
+            *presets = None;
+        }
+    }
+
+    ///////////////////
+    // Grouping
+    ///////////////////
+
+    /// Add a secondary player to the group this player is the primary player of
+    /// - group: Optional name of the group
+    ///
+    /// Returns the secondary players that were added
+    pub async fn add_slave(
+        &self,
+        slave: impl Into<PlayerAddress>,
+        group: Option<&str>,
+    ) -> Result<Vec<SyncSlave>, Error> {
+        let slave = slave.into();
+        let mut cmd = self.cmd("AddSlave");
+        cmd.add_param("slave", &slave.host);
+        cmd.add_param("port", slave.port);
+        cmd.add_optional("group", group);
+
+        let response: AddSlaveResponse = self.command_response(cmd).await?;
+        Ok(response.slaves)
+    }
+
+    /// Add several secondary players to the group this player is the primary player of
+    /// - group: Optional name of the group
+    ///
+    /// Returns the secondary players that were added
+    pub async fn add_slaves<P: Into<PlayerAddress>>(
+        &self,
+        slaves: impl IntoIterator<Item = P>,
+        group: Option<&str>,
+    ) -> Result<Vec<SyncSlave>, Error> {
+        let slaves: Vec<PlayerAddress> = slaves.into_iter().map(Into::into).collect();
+        let hosts: Vec<&str> = slaves.iter().map(|s| s.host.as_str()).collect();
+        let ports: Vec<String> = slaves.iter().map(|s| s.port.to_string()).collect();
+
+        let mut cmd = self.cmd("AddSlaves");
+        cmd.add_param("slaves", hosts.join(","));
+        cmd.add_param("ports", ports.join(","));
+        cmd.add_optional("group", group);
+
+        let response: AddSlaveResponse = self.command_response(cmd).await?;
+        Ok(response.slaves)
+    }
+
+    /// Remove a secondary player from the group this player is the primary player of
+    ///
+    /// Returns the sync status of this player after the removal
+    pub async fn remove_slave(&self, slave: impl Into<PlayerAddress>) -> Result<SyncStatus, Error> {
+        let slave = slave.into();
+        let mut cmd = self.cmd("RemoveSlave");
+        cmd.add_param("slave", &slave.host);
+        cmd.add_param("port", slave.port);
+
+        let sync_status: SyncStatus = self.command_response(cmd).await?;
+        Ok(sync_status)
+    }
+
     ///////////////////
     // Play Queue Management
     ///////////////////
 
     /// Get the current play queue from the BluOS device
     pub async fn queue(&self, pagination: Option<Pagination>) -> Result<Playlist, Error> {
-        let mut cmd = self.cmd("Playlist");
-        match pagination {
-            Some(p) => {
-                cmd.add_param("start", p.start);
-                cmd.add_param("end", p.end);
-            }
-            None => {}
-        };
+        let mut cmd = self.read_cmd("Playlist");
+        if let Some(p) = pagination {
+            cmd.add_param("start", p.start);
+            cmd.add_param("end", p.end);
+        }
         let pl: Playlist = self.command_response(cmd).await?;
 
         Ok(pl)
@@ -220,13 +639,253 @@ impl BluOS {
     }
 }
 
+/// Port BluOS players listen on
+const DEFAULT_PORT: u16 = 11000;
+/// How long the player may hold a long-polling /Status request before answering
+const LONG_POLL_TIMEOUT: Duration = Duration::from_secs(100);
+/// Upper bound for the reconnect delay of status_stream
+const MAX_STATUS_BACKOFF: Duration = Duration::from_secs(30);
+
+#[derive(Default)]
+struct StatusPoll {
+    etag: Option<String>,
+    failures: u32,
+}
+
+fn status_backoff(failures: u32) -> Duration {
+    let exp = failures.saturating_sub(1).min(5);
+    Duration::from_secs(1 << exp).min(MAX_STATUS_BACKOFF)
+}
+
+/// Range of the play queue to fetch, start and end are inclusive
 pub struct Pagination {
     start: u64,
     end: u64,
 }
 
+impl Pagination {
+    pub fn new(start: u64, end: u64) -> Pagination {
+        Pagination { start, end }
+    }
+}
+
 pub enum RepeatSetting {
     EntireQueue = 0,
     CurrentTrack = 1,
     Disable = 2,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{status_backoff, BluOS, Transport, TransportRequest, TransportResponse};
+    use crate::error::Error;
+    use futures::executor::block_on;
+    use futures::future::BoxFuture;
+    use futures::StreamExt;
+    use std::collections::VecDeque;
+    #[cfg(feature = "reqwest")]
+    use std::net::Ipv6Addr;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    /// Answers with the given bodies in order and records the path and query of the requests
+    #[derive(Debug)]
+    struct CannedTransport {
+        bodies: Mutex<VecDeque<&'static str>>,
+        requests: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Transport for CannedTransport {
+        fn get(
+            &self,
+            request: TransportRequest,
+        ) -> BoxFuture<'_, Result<TransportResponse, Error>> {
+            self.requests.lock().unwrap().push(request.path_and_query);
+            let body = self.bodies.lock().unwrap().pop_front().unwrap_or_default();
+            Box::pin(async move {
+                Ok(TransportResponse {
+                    status: 200,
+                    body: body.to_string(),
+                })
+            })
+        }
+    }
+
+    fn canned(bodies: &[&'static str]) -> (BluOS, Arc<Mutex<Vec<String>>>) {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let bluos = BluOS::builder()
+            .hostname("kitchen.local")
+            .transport(CannedTransport {
+                bodies: Mutex::new(bodies.iter().copied().collect()),
+                requests: requests.clone(),
+            })
+            .build()
+            .unwrap();
+        (bluos, requests)
+    }
+
+    #[cfg(feature = "reqwest")]
+    fn base_url(bluos: &BluOS) -> String {
+        bluos.cmd("Status").build().unwrap().to_string()
+    }
+
+    #[test]
+    #[cfg(feature = "reqwest")]
+    fn hostname_constructors() {
+        let bluos = BluOS::with_hostname("Kitchen.local", None).unwrap();
+        assert_eq!(base_url(&bluos), "http://kitchen.local:11000/Status");
+
+        let bluos = BluOS::with_hostname("[fe80::1]", Some(11010)).unwrap();
+        assert_eq!(base_url(&bluos), "http://[fe80::1]:11010/Status");
+
+        let bluos = BluOS::with_ipv6(Ipv6Addr::LOCALHOST, None).unwrap();
+        assert_eq!(base_url(&bluos), "http://[::1]:11000/Status");
+
+        for hostname in ["", "kitchen local", "kitchen/status", "kitchen:11000"] {
+            assert!(
+                matches!(
+                    BluOS::with_hostname(hostname, None),
+                    Err(Error::AddressError { .. })
+                ),
+                "{}",
+                hostname
+            );
+        }
+        assert!(BluOS::with_hostname("kitchen.local", Some(0)).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "reqwest")]
+    fn url_constructor() {
+        let bluos = BluOS::with_url("http://192.168.1.100:11000").unwrap();
+        assert_eq!(base_url(&bluos), "http://192.168.1.100:11000/Status");
+
+        let bluos = BluOS::with_url("http://[fe80::1]/").unwrap();
+        assert_eq!(base_url(&bluos), "http://[fe80::1]:11000/Status");
+
+        for url in [
+            "192.168.1.100",
+            "https://192.168.1.100",
+            "http://192.168.1.100:11000/Status",
+            "http://user@192.168.1.100",
+        ] {
+            assert!(
+                matches!(BluOS::with_url(url), Err(Error::AddressError { .. })),
+                "{}",
+                url
+            );
+        }
+    }
+
+    #[test]
+    fn user_agent() {
+        let builder = BluOS::builder().hostname("kitchen.local");
+        for user_agent in ["bluos\r\nX-Forwarded-For: 10.0.0.1", "bluos\u{7f}"] {
+            assert!(
+                matches!(
+                    builder.clone().user_agent(user_agent).build(),
+                    Err(Error::HeaderError(_))
+                ),
+                "{:?}",
+                user_agent
+            );
+        }
+        assert!(!matches!(
+            builder
+                .user_agent("bluos-api-rs/1.1 (Linux;\tcafé)")
+                .build(),
+            Err(Error::HeaderError(_))
+        ));
+    }
+
+    #[test]
+    fn volume_commands() {
+        const MUTED: &str =
+            r#"<volume db="-100" mute="1" muteDb="-20.5" muteVolume="30" etag="5b1">0</volume>"#;
+        let (bluos, requests) = canned(&[MUTED, MUTED, MUTED, MUTED]);
+
+        let volume = block_on(bluos.set_volume(150, Some(true))).unwrap();
+        assert_eq!(volume.volume, 0);
+        assert_eq!(volume.mute, 1);
+        assert_eq!(volume.muted_volume, Some(30));
+        assert_eq!(volume.muted_decibel, Some(-20.5));
+        assert_eq!(volume.etag.as_deref(), Some("5b1"));
+
+        block_on(bluos.set_volume_db(-20.5, None)).unwrap();
+        block_on(bluos.step_volume_db(2.0, Some(false))).unwrap();
+        block_on(bluos.mute(None)).unwrap();
+        assert_eq!(
+            *requests.lock().unwrap(),
+            [
+                "/Volume?level=100&tell_slaves=1",
+                "/Volume?abs_db=-20.5",
+                "/Volume?db=2&tell_slaves=0",
+                "/Volume?mute=1",
+            ]
+        );
+    }
+
+    #[test]
+    fn status_stream_long_poll_parameters() {
+        const FIRST: &str = r#"<status etag="a1"><volume>20</volume><db>-30</db><mute>0</mute><repeat>2</repeat><shuffle>0</shuffle><song>0</song><mode>1</mode><pid>1</pid><prid>0</prid><sid>1</sid><state>stop</state><syncStat>1</syncStat><indexing>0</indexing><mid>1</mid></status>"#;
+        const SECOND: &str = r#"<status etag="b2"><volume>20</volume><db>-30</db><mute>0</mute><repeat>2</repeat><shuffle>0</shuffle><song>0</song><mode>1</mode><pid>1</pid><prid>0</prid><sid>1</sid><state>play</state><syncStat>1</syncStat><indexing>0</indexing><mid>1</mid></status>"#;
+        // The second answer is a long poll that timed out without a change
+        let (bluos, requests) = canned(&[FIRST, FIRST, SECOND]);
+
+        let states: Vec<String> = block_on(bluos.status_stream().take(2).collect::<Vec<_>>())
+            .into_iter()
+            .map(|status| status.unwrap().state)
+            .collect();
+        assert_eq!(states, ["stop", "play"]);
+        assert_eq!(
+            *requests.lock().unwrap(),
+            [
+                "/Status",
+                "/Status?timeout=100&etag=a1",
+                "/Status?timeout=100&etag=a1"
+            ]
+        );
+    }
+
+    #[test]
+    fn status_stream_backoff() {
+        assert_eq!(status_backoff(1), Duration::from_secs(1));
+        assert_eq!(status_backoff(2), Duration::from_secs(2));
+        assert_eq!(status_backoff(5), Duration::from_secs(16));
+        assert_eq!(status_backoff(6), Duration::from_secs(30));
+        assert_eq!(status_backoff(u32::MAX), Duration::from_secs(30));
+    }
+
+    #[test]
+    fn presets() {
+        const PRESETS: &str = r#"<presets prid="2"><preset id="1" name="Radio Paradise" url="RadioParadise:/0:4" image="/images/RadioParadiseIcon.png"/><preset id="2" name="Optical Input" url="Capture:hw:1,0/1/25/2"/></presets>"#;
+        const STATUS: &str = r#"<status etag="a1"><volume>20</volume><db>-30</db><mute>0</mute><repeat>2</repeat><shuffle>0</shuffle><song>0</song><mode>1</mode><pid>1</pid><prid>3</prid><sid>1</sid><state>stop</state><syncStat>1</syncStat><indexing>0</indexing><mid>1</mid></status>"#;
+        let (bluos, requests) = canned(&[PRESETS, STATUS, PRESETS, "", "", ""]);
+
+        let presets = block_on(bluos.presets()).unwrap();
+        assert_eq!(presets.prid, 2);
+        assert_eq!(presets.entries.len(), 2);
+        assert_eq!(presets.entries[0].url, "RadioParadise:/0:4");
+        assert_eq!(presets.entries[1].image, None);
+
+        // Cached until the status reports another prid
+        block_on(bluos.presets()).unwrap();
+        block_on(bluos.status()).unwrap();
+        block_on(bluos.presets()).unwrap();
+
+        block_on(bluos.load_preset(2)).unwrap();
+        block_on(bluos.next_preset()).unwrap();
+        block_on(bluos.previous_preset()).unwrap();
+        assert_eq!(
+            *requests.lock().unwrap(),
+            [
+                "/Presets",
+                "/Status",
+                "/Presets",
+                "/Preset?id=2",
+                "/Preset?id=%2B1",
+                "/Preset?id=-1",
+            ]
+        );
+    }
+}
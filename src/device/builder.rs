@@ -0,0 +1,253 @@
+#[cfg(feature = "reqwest")]
+use super::ReqwestTransport;
+use super::{BluOS, RetryPolicy, Transport, DEFAULT_PORT, LONG_POLL_TIMEOUT};
+use crate::error::Error;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use url::{Host, Url};
+
+/// Requests fail after this long unless another timeout is given
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Clone)]
+enum Address {
+    Unset,
+    Hostname(String),
+    Url(String),
+}
+
+/// Builder for a [BluOS] client with custom settings
+///
+/// ```no_run
+/// # use bluos_api_rs::BluOS;
+/// # use std::time::Duration;
+/// # fn main() -> Result<(), bluos_api_rs::Error> {
+/// let bluos = BluOS::builder()
+///     .hostname("kitchen.local")
+///     .timeout(Duration::from_secs(3))
+///     .tell_slaves(true)
+///     .build()?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct BluOSBuilder {
+    address: Address,
+    port: Option<u16>,
+    transport: Option<Arc<dyn Transport>>,
+    timeout: Duration,
+    long_poll_timeout: Duration,
+    user_agent: Option<String>,
+    tell_slaves: Option<bool>,
+    retry: RetryPolicy,
+}
+
+impl BluOSBuilder {
+    pub fn new() -> BluOSBuilder {
+        BluOSBuilder {
+            address: Address::Unset,
+            port: None,
+            transport: None,
+            timeout: DEFAULT_TIMEOUT,
+            long_poll_timeout: LONG_POLL_TIMEOUT,
+            user_agent: None,
+            tell_slaves: None,
+            retry: RetryPolicy::default(),
+        }
+    }
+
+    /// Hostname such as `kitchen.local`, or an IP address. IPv6 addresses may be bracketed
+    pub fn hostname(mut self, hostname: &str) -> BluOSBuilder {
+        self.address = Address::Hostname(hostname.to_string());
+        self
+    }
+
+    pub fn ip(self, addr: IpAddr) -> BluOSBuilder {
+        self.hostname(&addr.to_string())
+    }
+
+    pub fn socket_addr(self, addr: SocketAddr) -> BluOSBuilder {
+        self.ip(addr.ip()).port(addr.port())
+    }
+
+    /// Base URL such as `http://192.168.1.100:11000`
+    ///
+    /// Only plain http URLs without a path are accepted. Without a port the BluOS port 11000 is used
+    pub fn url(mut self, base_url: &str) -> BluOSBuilder {
+        self.address = Address::Url(base_url.to_string());
+        self
+    }
+
+    /// Port of the player, overrides the port of the URL. Defaults to 11000
+    pub fn port(mut self, port: u16) -> BluOSBuilder {
+        self.port = Some(port);
+        self
+    }
+
+    /// Send requests with this client, for example to share a connection pool
+    #[cfg(feature = "reqwest")]
+    pub fn client(self, client: reqwest::Client) -> BluOSBuilder {
+        self.transport(ReqwestTransport::new(client))
+    }
+
+    /// Send requests with your own [Transport] instead of reqwest, for example to tunnel them
+    /// through a gateway or replay recorded responses in tests
+    pub fn transport(mut self, transport: impl Transport + 'static) -> BluOSBuilder {
+        self.transport = Some(Arc::new(transport));
+        self
+    }
+
+    /// Timeout of a single request. Defaults to 10 seconds
+    pub fn timeout(mut self, timeout: Duration) -> BluOSBuilder {
+        self.timeout = timeout;
+        self
+    }
+
+    /// How long the player may hold a long-polling /Status request of
+    /// [status_stream](BluOS::status_stream) before answering. Defaults to 100 seconds
+    ///
+    /// The request timeout is added on top of this for the request itself. The player only
+    /// takes whole seconds, shorter timeouts are raised to 1 second
+    pub fn long_poll_timeout(mut self, timeout: Duration) -> BluOSBuilder {
+        self.long_poll_timeout = timeout.max(Duration::from_secs(1));
+        self
+    }
+
+    /// User agent sent with every request
+    pub fn user_agent(mut self, user_agent: &str) -> BluOSBuilder {
+        self.user_agent = Some(user_agent.to_string());
+        self
+    }
+
+    /// Whether volume changes apply to the whole group when a method is not told otherwise.
+    /// By default the player decides
+    pub fn tell_slaves(mut self, tell_slaves: bool) -> BluOSBuilder {
+        self.tell_slaves = Some(tell_slaves);
+        self
+    }
+
+    /// How requests that only read from the player are retried, see [RetryPolicy]
+    pub fn retry_policy(mut self, retry: RetryPolicy) -> BluOSBuilder {
+        self.retry = retry;
+        self
+    }
+
+    /// Validate the settings and create the client
+    pub fn build(self) -> Result<BluOS, Error> {
+        let (hostname, url_port) = match &self.address {
+            Address::Hostname(hostname) => (validate_host(hostname)?, None),
+            Address::Url(base_url) => parse_base_url(base_url)?,
+            Address::Unset => {
+                return Err(Error::AddressError {
+                    address: String::new(),
+                    reason: "no hostname or URL given",
+                })
+            }
+        };
+
+        let port = self.port.or(url_port).unwrap_or(DEFAULT_PORT);
+        if port == 0 {
+            return Err(Error::AddressError {
+                address: format!("{}:{}", hostname, port),
+                reason: "port 0 is not valid",
+            });
+        }
+
+        // Visible ASCII, spaces and tabs, the same as a header value may contain. Bytes of
+        // UTF-8 characters are accepted like reqwest does
+        if let Some(user_agent) = &self.user_agent {
+            if user_agent
+                .bytes()
+                .any(|b| (b < b' ' && b != b'\t') || b == 0x7f)
+            {
+                return Err(Error::HeaderError(user_agent.clone()));
+            }
+        }
+
+        Ok(BluOS {
+            hostname,
+            port,
+            transport: match self.transport {
+                Some(transport) => transport,
+                None => default_transport()?,
+            },
+            timeout: self.timeout,
+            long_poll_timeout: self.long_poll_timeout,
+            user_agent: self.user_agent,
+            tell_slaves: self.tell_slaves,
+            retry: self.retry,
+            presets: Mutex::new(None),
+        })
+    }
+}
+
+impl Default for BluOSBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "reqwest")]
+fn default_transport() -> Result<Arc<dyn Transport>, Error> {
+    Ok(Arc::new(ReqwestTransport::default()))
+}
+
+#[cfg(not(feature = "reqwest"))]
+fn default_transport() -> Result<Arc<dyn Transport>, Error> {
+    Err(Error::NoTransportError)
+}
+
+/// Checks that the host can be used in a URL. IP addresses are normalized, IPv6 addresses
+/// are returned without brackets as those are added when a request is built
+fn validate_host(host: &str) -> Result<String, Error> {
+    let invalid = |reason| Error::AddressError {
+        address: host.to_string(),
+        reason,
+    };
+
+    let unbracketed = host
+        .strip_prefix('[')
+        .and_then(|h| h.strip_suffix(']'))
+        .unwrap_or(host);
+    if let Ok(addr) = unbracketed.parse::<IpAddr>() {
+        return Ok(addr.to_string());
+    }
+    if host.is_empty() {
+        return Err(invalid("the hostname is empty"));
+    }
+
+    match Host::parse(host) {
+        Ok(Host::Domain(domain)) => Ok(domain),
+        Ok(Host::Ipv4(addr)) => Ok(addr.to_string()),
+        Ok(Host::Ipv6(addr)) => Ok(addr.to_string()),
+        Err(_) => Err(invalid("not a valid hostname")),
+    }
+}
+
+/// Splits a base URL into the host and the port, if it has one
+fn parse_base_url(base_url: &str) -> Result<(String, Option<u16>), Error> {
+    let invalid = |reason| Error::AddressError {
+        address: base_url.to_string(),
+        reason,
+    };
+
+    let url = Url::parse(base_url).map_err(|_| invalid("not a URL"))?;
+    if url.scheme() != "http" {
+        return Err(invalid("only http is supported"));
+    }
+    if !url.username().is_empty() || url.password().is_some() {
+        return Err(invalid("credentials are not supported"));
+    }
+    if url.path() != "/" || url.query().is_some() || url.fragment().is_some() {
+        return Err(invalid("the URL must not have a path"));
+    }
+    let hostname = match url.host() {
+        Some(Host::Domain(domain)) => domain.to_string(),
+        Some(Host::Ipv4(addr)) => addr.to_string(),
+        Some(Host::Ipv6(addr)) => addr.to_string(),
+        None => return Err(invalid("the URL has no host")),
+    };
+
+    Ok((hostname, url.port()))
+}
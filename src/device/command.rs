@@ -1,11 +1,25 @@
-use std::collections::HashMap;
+use crate::error::Error;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use std::net::Ipv6Addr;
 use std::string::ToString;
+use std::time::Duration;
+use url::{Position, Url};
+
+/// Everything but the unreserved characters of RFC 3986 gets percent-encoded,
+/// so browse keys containing &, =, + or : survive the round trip to the device
+const QUERY_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
 
 pub struct Command {
     hostname: String,
     port: u16,
     command_name: String,
-    params: HashMap<String, String>,
+    params: Vec<(String, String)>,
+    timeout: Option<Duration>,
+    idempotent: bool,
 }
 
 impl Command {
@@ -15,46 +29,195 @@ impl Command {
             hostname: hostname.to_string(),
             port,
             command_name: command_name.to_string(),
-            params: HashMap::new(),
+            params: Vec::new(),
+            timeout: None,
+            idempotent: false,
+        }
+    }
+
+    /// Create a command from a path relative to the device, such as the
+    /// playURL of a browse item, e.g. `/Play?url=...`
+    ///
+    /// The query of the path is percent-decoded, it is encoded again when the command is built.
+    /// A `+` is kept as it is, players don't use it for spaces
+    pub fn from_path(hostname: &str, port: u16, path: &str) -> Command {
+        let path = path.trim_start_matches('/');
+        let (command_name, query) = path.split_once('?').unwrap_or((path, ""));
+
+        let mut cmd = Command::new(hostname, port, command_name);
+        for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            cmd.add_param(
+                &percent_decode_str(key).decode_utf8_lossy(),
+                percent_decode_str(value).decode_utf8_lossy(),
+            );
         }
+        cmd
     }
 
+    /// Name of the endpoint the command is sent to
+    pub fn name(&self) -> &str {
+        &self.command_name
+    }
+
+    /// Add a query parameter, parameters keep the order they were added in
+    /// and the same key can be added more than once
     pub fn add_param<T: ToString>(&mut self, param: &str, value: T) {
-        self.params.insert(param.to_string(), value.to_string());
+        self.params.push((param.to_string(), value.to_string()));
     }
 
     pub fn add_optional<T: ToString>(&mut self, param: &str, value: Option<T>) {
+        if let Some(v) = value {
+            self.add_param(param, v)
+        }
+    }
+
+    /// Use another timeout than the one of the client, e.g. for long-polling requests
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = Some(timeout);
+    }
+
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    /// Mark the command as safe to send more than once, it is retried if it fails
+    pub fn set_idempotent(&mut self) {
+        self.idempotent = true;
+    }
+
+    pub fn is_idempotent(&self) -> bool {
+        self.idempotent
+    }
+
+    pub fn build(&self) -> Result<Url, Error> {
+        let mut url = Url::parse("http://localhost/")?;
+
+        // IPv6 addresses have to be bracketed in URLs
+        match self.hostname.parse::<Ipv6Addr>() {
+            Ok(ip) => url.set_host(Some(&format!("[{}]", ip)))?,
+            Err(_) => url.set_host(Some(&self.hostname))?,
+        }
+        url.set_port(Some(self.port))
+            .map_err(|_| url::ParseError::InvalidPort)?;
+        url.set_path(&self.command_name);
+
+        if !self.params.is_empty() {
+            let query: Vec<String> = self
+                .params
+                .iter()
+                .map(|(key, value)| {
+                    format!(
+                        "{}={}",
+                        utf8_percent_encode(key, QUERY_ENCODE_SET),
+                        utf8_percent_encode(value, QUERY_ENCODE_SET)
+                    )
+                })
+                .collect();
+            url.set_query(Some(&query.join("&")));
+        }
+
+        Ok(url)
+    }
+
+    /// The encoded path and query the command is sent to, e.g. `/Status?timeout=100`
+    pub fn path_and_query(&self) -> Result<String, Error> {
+        Ok(self.build()?[Position::BeforePath..].to_string())
+    }
+}
+
+/// A command for an endpoint the crate has no method for, sent with
+/// [send_raw](crate::BluOS::send_raw) or [send_raw_xml](crate::BluOS::send_raw_xml)
+///
+/// ```
+/// # use bluos_api_rs::RawCommand;
+/// let cmd = RawCommand::new("Songs")
+///     .param("service", "LocalMusic")
+///     .param("start", 0)
+///     .idempotent();
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct RawCommand {
+    endpoint: String,
+    params: Vec<(String, String)>,
+    idempotent: bool,
+    timeout: Option<Duration>,
+}
+
+impl RawCommand {
+    /// Command for an endpoint such as `Status` or `/Status`
+    pub fn new(endpoint: &str) -> RawCommand {
+        RawCommand {
+            endpoint: endpoint.trim_start_matches('/').to_string(),
+            params: Vec::new(),
+            idempotent: false,
+            timeout: None,
+        }
+    }
+
+    /// Add a query parameter, it is percent-encoded when sent.
+    /// Parameters keep their order and the same key can be added more than once
+    pub fn param<T: ToString>(mut self, param: &str, value: T) -> RawCommand {
+        self.params.push((param.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn optional_param<T: ToString>(self, param: &str, value: Option<T>) -> RawCommand {
         match value {
-            Some(v) => self.add_param(param, v),
-            None => {}
+            Some(v) => self.param(param, v),
+            None => self,
         }
     }
 
-    pub fn build(&self) -> String {
-        let base = format!(
-            "http://{}:{}/{}",
-            self.hostname, self.port, self.command_name
-        );
-        let mut query = String::new();
-        if self.params.len() > 0 {
-            query.push('?');
-            for (key, value) in self.params.iter() {
-                query.push_str(format!("{}={}&", key, value).as_str());
-            }
+    /// Allow the command to be retried if it fails. Only use this for commands that
+    /// don't change the player, a retry may repeat a command that already reached it
+    pub fn idempotent(mut self) -> RawCommand {
+        self.idempotent = true;
+        self
+    }
+
+    /// Use another timeout than the one of the client
+    pub fn timeout(mut self, timeout: Duration) -> RawCommand {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub(crate) fn into_command(self, hostname: &str, port: u16) -> Command {
+        Command {
+            hostname: hostname.to_string(),
+            port,
+            command_name: self.endpoint,
+            params: self.params,
+            timeout: self.timeout,
+            idempotent: self.idempotent,
         }
-        format!("{}{}", base, query)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::Command;
+    use super::{Command, RawCommand};
 
     #[test]
     fn simple_command() {
         let cmd = Command::new("korv", 1515, "Hello");
-        let result = cmd.build();
-        assert_eq!(result, "http://korv:1515/Hello")
+        let result = cmd.build().unwrap();
+        assert_eq!(result.as_str(), "http://korv:1515/Hello")
+    }
+
+    #[test]
+    fn raw_command() {
+        let cmd = RawCommand::new("/Songs")
+            .param("service", "LocalMusic")
+            .optional_param("start", None::<u32>)
+            .param("album", "Kind of Blue")
+            .idempotent()
+            .into_command("korv", 11000);
+        assert!(cmd.is_idempotent());
+        assert_eq!(
+            cmd.build().unwrap().as_str(),
+            "http://korv:11000/Songs?service=LocalMusic&album=Kind%20of%20Blue"
+        );
     }
 
     #[test]
@@ -62,8 +225,78 @@ mod tests {
         let mut cmd = Command::new("korv", 10000, "Hello");
         cmd.add_param("int", 123);
         cmd.add_param("string", "lol");
-        let result = cmd.build();
-        assert!(result.contains("int=123"));
-        assert!(result.contains("string=lol"));
+        let result = cmd.build().unwrap();
+        assert_eq!(
+            result.as_str(),
+            "http://korv:10000/Hello?int=123&string=lol"
+        );
+    }
+
+    #[test]
+    fn repeated_params_keep_order() {
+        let mut cmd = Command::new("korv", 11000, "Hello");
+        cmd.add_param("b", 1);
+        cmd.add_param("a", 2);
+        cmd.add_param("b", 3);
+        let result = cmd.build().unwrap();
+        assert_eq!(result.query(), Some("b=1&a=2&b=3"));
+    }
+
+    #[test]
+    fn hostile_browse_keys() {
+        let keys = [
+            "Tidal:menu/genres&lang=en",
+            "LocalMusic:bySection/%2FAlbums%3Fservice%3DLocalMusic",
+            "Spotify:search?q=miles davis&type=artist",
+            "a+b=c;d#e",
+        ];
+        for key in keys {
+            let mut cmd = Command::new("10.0.1.36", 11000, "Browse");
+            cmd.add_param("key", key);
+            let result = cmd.build().unwrap();
+
+            let pairs: Vec<(String, String)> = result.query_pairs().into_owned().collect();
+            assert_eq!(pairs, vec![("key".to_string(), key.to_string())]);
+        }
+    }
+
+    #[test]
+    fn ipv6_host() {
+        let cmd = Command::new("fe80::1c2b:3cff:fe4d:5e6f", 11000, "Status");
+        let result = cmd.build().unwrap();
+        assert_eq!(
+            result.as_str(),
+            "http://[fe80::1c2b:3cff:fe4d:5e6f]:11000/Status"
+        );
+    }
+
+    #[test]
+    fn invalid_host() {
+        let cmd = Command::new("korv/../evil", 11000, "Status");
+        assert!(cmd.build().is_err());
+    }
+
+    #[test]
+    fn command_from_path() {
+        let cmd = Command::from_path("korv", 11000, "/Play?url=Tidal%3Atrack%2F1234&id=1");
+        let result = cmd.build().unwrap();
+        assert_eq!(
+            result.as_str(),
+            "http://korv:11000/Play?url=Tidal%3Atrack%2F1234&id=1"
+        )
+    }
+
+    #[test]
+    fn command_from_path_keeps_plus() {
+        let cmd = Command::from_path(
+            "korv",
+            11000,
+            "/Play?url=Radio+Paradise%3A%2Fmain+mix&name=A%20B+C",
+        );
+        let result = cmd.build().unwrap();
+        assert_eq!(
+            result.as_str(),
+            "http://korv:11000/Play?url=Radio%2BParadise%3A%2Fmain%2Bmix&name=A%20B%2BC"
+        )
     }
 }
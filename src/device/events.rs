@@ -0,0 +1,273 @@
+use super::Status;
+
+/// A change between two consecutive Status snapshots of a BluOS device
+#[derive(Debug, Clone, PartialEq)]
+pub enum StatusEvent {
+    /// A different track (or stream metadata) is now active
+    TrackChanged {
+        /// Position of the new track in the play queue
+        song: i64,
+        title: Option<String>,
+        artist: Option<String>,
+        album: Option<String>,
+    },
+    /// The player state changed, for example from pause to play
+    StateChanged { from: String, to: String },
+    /// The volume changed, in percentage and decibel
+    VolumeChanged { volume: i64, decibel: f64 },
+    /// The player was muted or unmuted
+    MuteChanged { muted: bool },
+    /// The play queue was modified, pid is the new play queue id
+    QueueChanged { pid: i64 },
+    /// The presets were modified, any cached preset list should be purged
+    PresetsChanged { prid: u8 },
+    /// Shuffle was enabled or disabled
+    ShuffleChanged { enabled: bool },
+    /// 0 means repeat play queue, 1 means repeat a track, and 2 means repeat off
+    RepeatChanged { repeat: u8 },
+    /// The group this player is the primary player of changed
+    GroupChanged { group_name: Option<String> },
+    /// The player started indexing the library
+    IndexingStarted,
+    /// The player finished indexing the library
+    IndexingFinished,
+}
+
+impl StatusEvent {
+    /// Compare two Status snapshots and return what changed from previous to current
+    pub fn diff(previous: &Status, current: &Status) -> Vec<StatusEvent> {
+        let mut events = Vec::new();
+
+        if previous.song_queue_position != current.song_queue_position
+            || previous.name != current.name
+            || previous.artist != current.artist
+            || previous.album != current.album
+            || previous.title1 != current.title1
+        {
+            events.push(StatusEvent::TrackChanged {
+                song: current.song_queue_position,
+                title: current.name.clone().or_else(|| current.title1.clone()),
+                artist: current.artist.clone(),
+                album: current.album.clone(),
+            });
+        }
+
+        if previous.state != current.state {
+            events.push(StatusEvent::StateChanged {
+                from: previous.state.clone(),
+                to: current.state.clone(),
+            });
+        }
+
+        if previous.volume != current.volume || previous.volume_decibel != current.volume_decibel {
+            events.push(StatusEvent::VolumeChanged {
+                volume: current.volume,
+                decibel: current.volume_decibel,
+            });
+        }
+
+        if previous.mute != current.mute {
+            events.push(StatusEvent::MuteChanged {
+                muted: current.mute == 1,
+            });
+        }
+
+        if previous.pid != current.pid {
+            events.push(StatusEvent::QueueChanged { pid: current.pid });
+        }
+
+        if previous.prid != current.prid {
+            events.push(StatusEvent::PresetsChanged { prid: current.prid });
+        }
+
+        if previous.shuffle != current.shuffle {
+            events.push(StatusEvent::ShuffleChanged {
+                enabled: current.shuffle == 1,
+            });
+        }
+
+        if previous.repeat != current.repeat {
+            events.push(StatusEvent::RepeatChanged {
+                repeat: current.repeat,
+            });
+        }
+
+        if previous.group_name != current.group_name {
+            events.push(StatusEvent::GroupChanged {
+                group_name: current.group_name.clone(),
+            });
+        }
+
+        match (previous.indexing != 0, current.indexing != 0) {
+            (false, true) => events.push(StatusEvent::IndexingStarted),
+            (true, false) => events.push(StatusEvent::IndexingFinished),
+            _ => {}
+        }
+
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::StatusEvent;
+    use crate::Status;
+
+    const STATUS: &str = r#"<status etag="4e266c9fbfba6d13d1a4d6ff4bd2e1e6">
+<album>Kind of Blue</album>
+<artist>Miles Davis</artist>
+<name>So What</name>
+<db>-27.5</db>
+<indexing>0</indexing>
+<mid>15</mid>
+<mode>1</mode>
+<mute>0</mute>
+<pid>88</pid>
+<prid>2</prid>
+<repeat>2</repeat>
+<shuffle>0</shuffle>
+<sid>5</sid>
+<song>0</song>
+<state>pause</state>
+<syncStat>61</syncStat>
+<title1>So What</title1>
+<volume>22</volume>
+</status>"#;
+
+    fn status() -> Status {
+        serde_xml_rs::from_str(STATUS).unwrap()
+    }
+
+    #[test]
+    fn identical_status_has_no_events() {
+        assert!(StatusEvent::diff(&status(), &status()).is_empty());
+    }
+
+    #[test]
+    fn status_changes() {
+        let previous = status();
+        let mut current = status();
+        current.state = "play".to_string();
+        current.song_queue_position = 1;
+        current.name = Some("Freddie Freeloader".to_string());
+        current.mute = 1;
+        current.indexing = 1;
+
+        assert_eq!(
+            StatusEvent::diff(&previous, &current),
+            vec![
+                StatusEvent::TrackChanged {
+                    song: 1,
+                    title: Some("Freddie Freeloader".to_string()),
+                    artist: Some("Miles Davis".to_string()),
+                    album: Some("Kind of Blue".to_string()),
+                },
+                StatusEvent::StateChanged {
+                    from: "pause".to_string(),
+                    to: "play".to_string(),
+                },
+                StatusEvent::MuteChanged { muted: true },
+                StatusEvent::IndexingStarted,
+            ]
+        );
+    }
+
+    /// Events between two copies of the base status, each with its own change applied
+    fn diff(
+        previous: impl FnOnce(&mut Status),
+        current: impl FnOnce(&mut Status),
+    ) -> Vec<StatusEvent> {
+        let (mut from, mut to) = (status(), status());
+        previous(&mut from);
+        current(&mut to);
+        StatusEvent::diff(&from, &to)
+    }
+
+    #[test]
+    fn single_changes() {
+        let unchanged = |_: &mut Status| {};
+
+        assert_eq!(
+            diff(unchanged, |s| s.title1 = Some("Radio Paradise".to_string())),
+            vec![StatusEvent::TrackChanged {
+                song: 0,
+                title: Some("So What".to_string()),
+                artist: Some("Miles Davis".to_string()),
+                album: Some("Kind of Blue".to_string()),
+            }]
+        );
+        assert_eq!(
+            diff(unchanged, |s| s.state = "stream".to_string()),
+            vec![StatusEvent::StateChanged {
+                from: "pause".to_string(),
+                to: "stream".to_string(),
+            }]
+        );
+        assert_eq!(
+            diff(unchanged, |s| s.volume = 30),
+            vec![StatusEvent::VolumeChanged {
+                volume: 30,
+                decibel: -27.5,
+            }]
+        );
+        assert_eq!(
+            diff(unchanged, |s| s.volume_decibel = -25.0),
+            vec![StatusEvent::VolumeChanged {
+                volume: 22,
+                decibel: -25.0,
+            }]
+        );
+        assert_eq!(
+            diff(unchanged, |s| s.pid = 89),
+            vec![StatusEvent::QueueChanged { pid: 89 }]
+        );
+        assert_eq!(
+            diff(unchanged, |s| s.prid = 3),
+            vec![StatusEvent::PresetsChanged { prid: 3 }]
+        );
+        assert_eq!(
+            diff(unchanged, |s| s.repeat = 0),
+            vec![StatusEvent::RepeatChanged { repeat: 0 }]
+        );
+        assert_eq!(
+            diff(unchanged, |s| s.group_name = Some("Downstairs".to_string())),
+            vec![StatusEvent::GroupChanged {
+                group_name: Some("Downstairs".to_string()),
+            }]
+        );
+        assert_eq!(
+            diff(|s| s.group_name = Some("Downstairs".to_string()), unchanged),
+            vec![StatusEvent::GroupChanged { group_name: None }]
+        );
+    }
+
+    #[test]
+    fn boolean_transitions() {
+        let on = |s: &mut Status| {
+            s.mute = 1;
+            s.shuffle = 1;
+            s.indexing = 1;
+        };
+        let off = |_: &mut Status| {};
+
+        assert_eq!(
+            diff(off, on),
+            vec![
+                StatusEvent::MuteChanged { muted: true },
+                StatusEvent::ShuffleChanged { enabled: true },
+                StatusEvent::IndexingStarted,
+            ]
+        );
+        assert_eq!(
+            diff(on, off),
+            vec![
+                StatusEvent::MuteChanged { muted: false },
+                StatusEvent::ShuffleChanged { enabled: false },
+                StatusEvent::IndexingFinished,
+            ]
+        );
+
+        // Any indexing count other than 0 means indexing is still running
+        assert!(diff(|s| s.indexing = 1, |s| s.indexing = 2).is_empty());
+    }
+}
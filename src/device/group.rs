@@ -0,0 +1,225 @@
+use super::{BluOS, SyncMaster, SyncSlave, SyncStatus, DEFAULT_PORT};
+use crate::error::Error;
+use std::fmt;
+use std::net::{Ipv6Addr, SocketAddr};
+
+/// Address of a BluOS player as used by the grouping endpoints
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PlayerAddress {
+    pub host: String,
+    pub port: u16,
+}
+
+impl PlayerAddress {
+    pub fn new(host: &str, port: u16) -> PlayerAddress {
+        PlayerAddress {
+            host: host.to_string(),
+            port,
+        }
+    }
+
+    /// Parse the `host:port` format used by the id attribute of /SyncStatus
+    ///
+    /// If there is no port the default BluOS port is assumed
+    pub fn parse(id: &str) -> PlayerAddress {
+        if let Ok(addr) = id.parse::<SocketAddr>() {
+            return addr.into();
+        }
+        match id.rsplit_once(':') {
+            Some((host, port)) if !host.contains(':') => match port.parse() {
+                Ok(port) => PlayerAddress::new(host, port),
+                Err(_) => PlayerAddress::new(id, DEFAULT_PORT),
+            },
+            _ => PlayerAddress::new(id, DEFAULT_PORT),
+        }
+    }
+}
+
+impl fmt::Display for PlayerAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Bracket IPv6 addresses so the port can be told apart
+        match self.host.parse::<Ipv6Addr>() {
+            Ok(_) => write!(f, "[{}]:{}", self.host, self.port),
+            Err(_) => write!(f, "{}:{}", self.host, self.port),
+        }
+    }
+}
+
+impl From<SocketAddr> for PlayerAddress {
+    fn from(addr: SocketAddr) -> Self {
+        PlayerAddress::new(&addr.ip().to_string(), addr.port())
+    }
+}
+
+impl From<&BluOS> for PlayerAddress {
+    fn from(b: &BluOS) -> Self {
+        PlayerAddress::new(&b.hostname, b.port)
+    }
+}
+
+impl From<&SyncSlave> for PlayerAddress {
+    fn from(s: &SyncSlave) -> Self {
+        PlayerAddress::new(&s.id, s.port)
+    }
+}
+
+impl From<&SyncMaster> for PlayerAddress {
+    fn from(m: &SyncMaster) -> Self {
+        PlayerAddress::new(&m.address, m.port)
+    }
+}
+
+/// A primary player and the secondary players grouped with it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlayerGroup {
+    /// Name of the group, if the primary player reported one
+    pub name: Option<String>,
+    pub master: PlayerAddress,
+    pub slaves: Vec<PlayerAddress>,
+}
+
+impl PlayerGroup {
+    /// True if the player is the primary or one of the secondary players of this group
+    pub fn contains(&self, player: &PlayerAddress) -> bool {
+        &self.master == player || self.slaves.contains(player)
+    }
+}
+
+/// The master/slave relationships between a set of BluOS players
+///
+/// Players that are not grouped show up as a group without slaves.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GroupTopology {
+    pub groups: Vec<PlayerGroup>,
+}
+
+impl GroupTopology {
+    /// Resolve the topology from the sync status of a set of players
+    pub fn from_sync_statuses<'a>(
+        statuses: impl IntoIterator<Item = &'a SyncStatus>,
+    ) -> GroupTopology {
+        let mut topology = GroupTopology::default();
+
+        for status in statuses {
+            let player = PlayerAddress::parse(&status.id);
+
+            match &status.master {
+                Some(master) => {
+                    let group = topology.group_mut(master.into());
+                    if !group.slaves.contains(&player) {
+                        group.slaves.push(player);
+                    }
+                }
+                None => {
+                    let group = topology.group_mut(player);
+                    if status.group.is_some() {
+                        group.name = status.group.clone();
+                    }
+                    for slave in &status.slaves {
+                        let slave = slave.into();
+                        if !group.slaves.contains(&slave) {
+                            group.slaves.push(slave);
+                        }
+                    }
+                }
+            }
+        }
+
+        topology
+    }
+
+    /// Fetch the sync status of every player and resolve the topology
+    pub async fn resolve(players: &[BluOS]) -> Result<GroupTopology, Error> {
+        let mut statuses = Vec::with_capacity(players.len());
+        for player in players {
+            statuses.push(player.sync_status().await?);
+        }
+
+        Ok(GroupTopology::from_sync_statuses(&statuses))
+    }
+
+    /// The group the player is part of
+    pub fn group_of(&self, player: &PlayerAddress) -> Option<&PlayerGroup> {
+        self.groups.iter().find(|g| g.contains(player))
+    }
+
+    /// The primary player of the group the player is part of
+    pub fn master_of(&self, player: &PlayerAddress) -> Option<&PlayerAddress> {
+        self.group_of(player).map(|g| &g.master)
+    }
+
+    fn group_mut(&mut self, master: PlayerAddress) -> &mut PlayerGroup {
+        // A player we saw as a slave first may turn out to be listed as a master
+        let position = match self.groups.iter().position(|g| g.master == master) {
+            Some(p) => p,
+            None => {
+                self.groups.push(PlayerGroup {
+                    name: None,
+                    master,
+                    slaves: Vec::new(),
+                });
+                self.groups.len() - 1
+            }
+        };
+        &mut self.groups[position]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{GroupTopology, PlayerAddress};
+    use crate::SyncStatus;
+
+    fn sync_status(xml: &str) -> SyncStatus {
+        serde_xml_rs::from_str(xml).unwrap()
+    }
+
+    #[test]
+    fn parse_player_address() {
+        assert_eq!(
+            PlayerAddress::parse("192.168.1.100:11000"),
+            PlayerAddress::new("192.168.1.100", 11000)
+        );
+        assert_eq!(
+            PlayerAddress::parse("kitchen.local"),
+            PlayerAddress::new("kitchen.local", 11000)
+        );
+
+        // IPv6 addresses are bracketed so they survive a round trip
+        let address = PlayerAddress::new("fe80::1", 11000);
+        assert_eq!(address.to_string(), "[fe80::1]:11000");
+        assert_eq!(PlayerAddress::parse(&address.to_string()), address);
+    }
+
+    #[test]
+    fn resolve_topology() {
+        let statuses = vec![
+            sync_status(
+                r#"<SyncStatus name="Kitchen" id="192.168.1.102:11000" volume="20">
+                <master port="11000">192.168.1.100</master></SyncStatus>"#,
+            ),
+            sync_status(
+                r#"<SyncStatus name="Living Room" id="192.168.1.100:11000" volume="20" group="Downstairs">
+                <slave id="192.168.1.102" port="11000"/><slave id="192.168.1.103" port="11000"/></SyncStatus>"#,
+            ),
+            sync_status(r#"<SyncStatus name="Office" id="192.168.1.104:11000" volume="20"/>"#),
+        ];
+
+        let topology = GroupTopology::from_sync_statuses(&statuses);
+        assert_eq!(topology.groups.len(), 2);
+
+        let downstairs = &topology.groups[0];
+        assert_eq!(downstairs.name.as_deref(), Some("Downstairs"));
+        assert_eq!(
+            downstairs.master,
+            PlayerAddress::new("192.168.1.100", 11000)
+        );
+        assert_eq!(downstairs.slaves.len(), 2);
+
+        assert_eq!(
+            topology.master_of(&PlayerAddress::new("192.168.1.103", 11000)),
+            Some(&PlayerAddress::new("192.168.1.100", 11000))
+        );
+        assert!(topology.groups[1].slaves.is_empty());
+    }
+}
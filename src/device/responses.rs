@@ -21,7 +21,7 @@ pub struct Status {
     pub muted_volume: Option<i64>,
     /// If the player is muted, then this contains the unmuted volume in dB.
     #[serde(rename = "muteDb")]
-    pub muted_decibel: Option<i64>,
+    pub muted_decibel: Option<f64>,
 
     ////////////////
     // Playback
@@ -75,8 +75,10 @@ pub struct Status {
     /// The first of two lines describing the current audio.
     /// twoline_title1 & twoline_title2, if present, MUST be used as the text of any UI that displays two
     /// lines of now-playing metadata.
+    #[serde(rename = "twoline_title1")]
     pub twoline_title1: Option<String>,
     /// The second of two lines describing the current audio.
+    #[serde(rename = "twoline_title2")]
     pub twoline_title2: Option<String>,
 
     /// What the player displays currently?
@@ -128,6 +130,30 @@ pub struct Status {
     pub mid: i64,
 }
 
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct VolumeResponse {
+    /// The player volume level in percentage
+    /// -1 means player volume fixed.
+    #[serde(rename = "$value")]
+    pub volume: i64,
+    /// Volume in decibel
+    #[serde(rename = "db")]
+    pub volume_decibel: f64,
+    /// Mute state. Set to 1 if volume is muted
+    pub mute: u8,
+    /// If the player is muted, then this contains the unmuted volume level.
+    /// Values are from 0 to 100.
+    #[serde(rename = "muteVolume")]
+    pub muted_volume: Option<i64>,
+    /// If the player is muted, then this contains the unmuted volume in dB.
+    #[serde(rename = "muteDb")]
+    pub muted_decibel: Option<f64>,
+    /// Volume offset in dB applied by the player, if any
+    pub offset_db: Option<f64>,
+    pub etag: Option<String>,
+}
+
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
 pub struct Actions {
     pub action: Vec<Action>,
@@ -135,8 +161,11 @@ pub struct Actions {
 
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
 pub struct Action {
-    pub hide: u8,
     pub name: String,
+    /// Set to 1 if the action should not be shown, most actions don't have it
+    pub hide: Option<u8>,
+    /// Endpoint to call for service specific actions such as skip on a radio station
+    pub url: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
@@ -171,14 +200,48 @@ impl From<String> for Quality {
     fn from(s: String) -> Self {
         use Quality::*;
 
-        return match s.as_str() {
+        match s.as_str() {
             "cd" => Cd,
             "hd" => Hd,
             "dolbyAudio" => DolbyAudio,
             "mqa" => Mqa,
             "mqaAuthored" => MqaAuthored,
             _ => Compressed(s.parse::<i64>().unwrap_or_default()),
-        };
+        }
+    }
+}
+
+/// Extract the message of an `<error>` document returned by a device that rejected a request
+///
+/// Returns None if the body is not an error document
+pub(crate) fn device_error_message(body: &str) -> Option<String> {
+    let mut body = body.trim_start();
+    if body.starts_with("<?xml") {
+        body = body[body.find("?>")? + 2..].trim_start();
+    }
+    let rest = body.strip_prefix("<error")?;
+    if !rest.starts_with(['>', '/', ' ', '\t', '\r', '\n']) {
+        return None;
+    }
+
+    // Collect all text content, this covers both <error>msg</error>
+    // and <error><message>msg</message></error>
+    let mut message = String::new();
+    let mut in_tag = false;
+    for c in body.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' => in_tag = false,
+            c if !in_tag => message.push(c),
+            _ => {}
+        }
+    }
+
+    let message = message.split_whitespace().collect::<Vec<_>>().join(" ");
+    if message.is_empty() {
+        Some("unknown error".to_string())
+    } else {
+        Some(message)
     }
 }
 
@@ -208,7 +271,7 @@ pub struct Playlist {
     pub modified: i64,
     /// total number of tracks in the current queue
     pub length: i64,
-    #[serde(rename = "$value")]
+    #[serde(rename = "$value", default)]
     pub entries: Vec<PlaylistEntry>,
 }
 
@@ -235,6 +298,144 @@ pub struct PlaylistEntry {
     pub quality: Option<Quality>,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SyncStatus {
+    /// Name of the player
+    pub name: String,
+    /// Address and port of the player, for example 192.168.1.100:11000
+    pub id: String,
+    /// MAC address of the player, stable even if the IP address changes
+    pub mac: Option<String>,
+    /// Model identifier, for example N130
+    pub model: Option<String>,
+    /// Human readable model name, for example NODE 2i
+    pub model_name: Option<String>,
+    /// Brand of the player, for example Bluesound or NAD
+    pub brand: Option<String>,
+    /// Version of the API schema the player implements
+    pub schema_version: Option<i64>,
+    /// Path of the player icon, relative to the player
+    pub icon: Option<String>,
+    /// Set to true once the player has been set up
+    pub initialized: Option<bool>,
+    pub etag: Option<String>,
+    pub sync_stat: Option<i64>,
+
+    ////////////////
+    // Volume
+    /////////////////
+    /// The player volume level in percentage
+    /// -1 means player volume fixed.
+    pub volume: i64,
+    /// Volume in decibel
+    #[serde(rename = "db")]
+    pub volume_decibel: Option<f64>,
+    /// Mute state. Set to 1 if volume is muted
+    pub mute: Option<u8>,
+    /// Output level in decibel
+    #[serde(rename = "outlevel")]
+    pub output_level: Option<f64>,
+
+    ////////////////
+    // Group
+    /////////////////
+    /// Name of the group the player belongs to
+    pub group: Option<String>,
+    /// The primary player of the group, only present if this player is a secondary player
+    pub master: Option<SyncMaster>,
+    /// The secondary players of the group, only present if this player is the primary player
+    #[serde(rename = "slave", default)]
+    pub slaves: Vec<SyncSlave>,
+
+    /// Battery state, only present on battery powered players
+    pub battery: Option<Battery>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SyncMaster {
+    /// Address of the primary player
+    #[serde(rename = "$value")]
+    pub address: String,
+    pub port: u16,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SyncSlave {
+    /// Address of the secondary player
+    pub id: String,
+    pub port: u16,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct AddSlaveResponse {
+    /// The secondary players that were added to the group
+    #[serde(rename = "slave", default)]
+    pub slaves: Vec<SyncSlave>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Battery {
+    /// Charge level in percentage
+    pub level: Option<i64>,
+    pub charging: Option<bool>,
+    pub icon: Option<String>,
+}
+
+/// Summary of the identity of a BluOS device, built from SyncStatus
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DeviceInfo {
+    pub name: String,
+    /// MAC address of the player, use this as a stable identity
+    pub mac: Option<String>,
+    pub model: Option<String>,
+    pub model_name: Option<String>,
+    pub brand: Option<String>,
+    pub schema_version: Option<i64>,
+    /// Path of the player icon, relative to the player
+    pub icon: Option<String>,
+}
+
+impl From<SyncStatus> for DeviceInfo {
+    fn from(s: SyncStatus) -> Self {
+        DeviceInfo {
+            name: s.name,
+            mac: s.mac,
+            model: s.model,
+            model_name: s.model_name,
+            brand: s.brand,
+            schema_version: s.schema_version,
+            icon: s.icon,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Presets {
+    /// The unique preset id. It matches the prid attribute in the /Status response.
+    /// If a preset is changed this number will change.
+    pub prid: u8,
+    #[serde(rename = "preset", default)]
+    pub entries: Vec<Preset>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Preset {
+    /// Preset number, used to load the preset
+    pub id: i64,
+    pub name: String,
+    /// URL of the preset, typically a service specific stream url
+    pub url: String,
+    /// URL of the image associated with the preset
+    pub image: Option<String>,
+}
+
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub struct Browse {
@@ -242,7 +443,7 @@ pub struct Browse {
     #[serde(rename = "type")]
     pub browse_type: String,
 
-    #[serde(rename = "$value")]
+    #[serde(rename = "$value", default)]
     pub items: Vec<BrowseItem>,
 }
 
@@ -260,8 +461,175 @@ pub struct BrowseItem {
     pub text2: Option<String>,
     #[serde(rename = "type")]
     pub item_type: Option<String>,
+    #[serde(rename = "playURL")]
     pub play_url: Option<String>,
+    #[serde(rename = "autoplayURL")]
     pub autoplay_url: Option<String>,
     pub context_menu_key: Option<String>,
+    #[serde(rename = "actionURL")]
     pub action_url: Option<String>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        device_error_message, Actions, Battery, Browse, DeviceInfo, IdResponse, Playlist, Presets,
+        StateResponse, Status, SyncMaster, SyncSlave, SyncStatus, VolumeResponse,
+    };
+    use serde::de::DeserializeOwned;
+    use std::fmt::Debug;
+    use std::path::Path;
+
+    type Parse = fn(&str) -> Result<String, serde_xml_rs::Error>;
+
+    /// Directories of tests/synthetic and the response type of the documents in them
+    const CORPUS: &[(&str, Parse)] = &[
+        ("browse", parse::<Browse>),
+        ("id", parse::<IdResponse>),
+        ("playlist", parse::<Playlist>),
+        ("presets", parse::<Presets>),
+        ("state", parse::<StateResponse>),
+        ("status", parse::<Status>),
+        ("sync_status", parse::<SyncStatus>),
+        ("volume", parse::<VolumeResponse>),
+    ];
+
+    fn parse<T: DeserializeOwned + Debug>(xml: &str) -> Result<String, serde_xml_rs::Error> {
+        Ok(format!("{:#?}", serde_xml_rs::from_str::<T>(xml)?))
+    }
+
+    /// Every document of the corpus has to parse and match its snapshot in
+    /// tests/synthetic/snapshots. Run with `INSTA_UPDATE=always` to accept changes
+    #[test]
+    fn synthetic_corpus() {
+        let corpus = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/synthetic");
+        for dir in std::fs::read_dir(&corpus).unwrap() {
+            let name = dir.unwrap().file_name().into_string().unwrap();
+            assert!(
+                name == "snapshots"
+                    || name == "README.md"
+                    || CORPUS.iter().any(|(d, _)| *d == name),
+                "no response type for synthetic/{}",
+                name
+            );
+        }
+
+        for (dir, parse) in CORPUS {
+            let mut documents: Vec<_> = std::fs::read_dir(corpus.join(dir))
+                .unwrap()
+                .map(|entry| entry.unwrap().path())
+                .collect();
+            documents.sort();
+            assert!(!documents.is_empty(), "synthetic/{} is empty", dir);
+
+            for document in documents {
+                let xml = std::fs::read_to_string(&document).unwrap();
+                let parsed = parse(&xml)
+                    .unwrap_or_else(|e| panic!("{} does not parse: {}", document.display(), e));
+
+                let stem = document.file_stem().unwrap().to_str().unwrap();
+                insta::with_settings!({
+                    snapshot_path => "../../tests/synthetic/snapshots",
+                    prepend_module_to_snapshot => false,
+                    input_file => &document,
+                }, {
+                    insta::assert_snapshot!(format!("{}__{}", dir, stem), parsed);
+                });
+            }
+        }
+    }
+
+    #[test]
+    fn error_documents() {
+        assert_eq!(
+            device_error_message("<error>Invalid key</error>").as_deref(),
+            Some("Invalid key")
+        );
+        assert_eq!(
+            device_error_message(
+                "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<error>\n  <message>Service unavailable</message>\n</error>"
+            )
+            .as_deref(),
+            Some("Service unavailable")
+        );
+        assert_eq!(
+            device_error_message("<error/>").as_deref(),
+            Some("unknown error")
+        );
+    }
+
+    #[test]
+    fn regular_documents() {
+        assert_eq!(device_error_message("<state>play</state>"), None);
+        assert_eq!(device_error_message("<errorlog>x</errorlog>"), None);
+    }
+
+    #[test]
+    fn status_fields() {
+        let status: Status = serde_xml_rs::from_str(
+            r#"<status etag="4e2"><volume>-1</volume><db>-32.5</db><mute>1</mute><muteDb>-22.5</muteDb><repeat>2</repeat><shuffle>0</shuffle><song>0</song><mode>1</mode><pid>1</pid><prid>0</prid><sid>1</sid><state>pause</state><syncStat>1</syncStat><indexing>0</indexing><mid>1</mid><twoline_title1>Song</twoline_title1><twoline_title2>Artist</twoline_title2></status>"#,
+        )
+        .unwrap();
+        assert_eq!(status.muted_decibel, Some(-22.5));
+        assert_eq!(status.twoline_title1.as_deref(), Some("Song"));
+        assert_eq!(status.twoline_title2.as_deref(), Some("Artist"));
+    }
+
+    #[test]
+    fn actions() {
+        let actions: Actions = serde_xml_rs::from_str(
+            r#"<actions><action name="back" hide="1"/><action name="skip" url="/Action?service=TuneIn&amp;skip=1"/></actions>"#,
+        )
+        .unwrap();
+        assert_eq!(actions.action[0].hide, Some(1));
+        assert_eq!(actions.action[1].hide, None);
+        assert_eq!(
+            actions.action[1].url.as_deref(),
+            Some("/Action?service=TuneIn&skip=1")
+        );
+    }
+
+    #[test]
+    fn sync_status() {
+        let sync_status: SyncStatus = serde_xml_rs::from_str(
+            r#"<SyncStatus name="Patio" id="192.168.1.101:11000" mac="90:56:82:ab:cd:ef" model="P125" modelName="PULSE FLEX 2i" brand="Bluesound" schemaVersion="32" icon="/images/players/P125_nt.png" initialized="true" volume="-1" db="-20" group="Outside"><master port="11000">192.168.1.100</master><slave id="192.168.1.102" port="11010"/><battery level="80" charging="true"/></SyncStatus>"#,
+        )
+        .unwrap();
+        assert_eq!(sync_status.volume, -1);
+        assert_eq!(
+            sync_status.master,
+            Some(SyncMaster {
+                address: "192.168.1.100".to_string(),
+                port: 11000,
+            })
+        );
+        assert_eq!(
+            sync_status.slaves,
+            vec![SyncSlave {
+                id: "192.168.1.102".to_string(),
+                port: 11010,
+            }]
+        );
+        assert_eq!(
+            sync_status.battery,
+            Some(Battery {
+                level: Some(80),
+                charging: Some(true),
+                icon: None,
+            })
+        );
+
+        assert_eq!(
+            DeviceInfo::from(sync_status),
+            DeviceInfo {
+                name: "Patio".to_string(),
+                mac: Some("90:56:82:ab:cd:ef".to_string()),
+                model: Some("P125".to_string()),
+                model_name: Some("PULSE FLEX 2i".to_string()),
+                brand: Some("Bluesound".to_string()),
+                schema_version: Some(32),
+                icon: Some("/images/players/P125_nt.png".to_string()),
+            }
+        );
+    }
+}
@@ -0,0 +1,127 @@
+use crate::error::Error;
+use std::time::Duration;
+
+/// How requests that only read from the player are retried when they fail
+///
+/// Wi-Fi players regularly drop a request or answer 503 while busy. Commands that change the
+/// player, such as Skip or Delete, are never retried since the first attempt may have
+/// reached the player.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    /// Attempts including the first one, 1 disables retries
+    pub max_attempts: u32,
+    /// Delay before the first retry, it doubles for every retry after that
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    /// Randomize the delays so players are not hit by several clients at the same time
+    pub jitter: bool,
+}
+
+impl RetryPolicy {
+    /// Never retry
+    pub fn none() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 1,
+            ..RetryPolicy::default()
+        }
+    }
+
+    /// Delay before the given retry, starting at 1
+    pub(crate) fn backoff(&self, retry: u32) -> Duration {
+        let exp = retry.saturating_sub(1).min(16);
+        let delay = self
+            .initial_backoff
+            .saturating_mul(1 << exp)
+            .min(self.max_backoff);
+
+        // Keep at least half of the delay so retries still back off
+        match self.jitter {
+            true => delay / 2 + delay.mul_f64(fastrand::f64() / 2.0),
+            false => delay,
+        }
+    }
+}
+
+impl Default for RetryPolicy {
+    /// 3 attempts, backing off from 250ms up to 2 seconds
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(250),
+            max_backoff: Duration::from_secs(2),
+            jitter: true,
+        }
+    }
+}
+
+/// Errors that may go away if the request is sent again
+pub(crate) fn is_transient(error: &Error) -> bool {
+    match error {
+        #[cfg(feature = "reqwest")]
+        Error::RequestError(e) => e.is_connect() || e.is_timeout() || e.is_request() || e.is_body(),
+        Error::TransportError(_) => true,
+        Error::HttpStatusError { status, .. } | Error::DeviceError { status, .. } => {
+            is_transient_status(*status)
+        }
+        _ => false,
+    }
+}
+
+/// Gateway errors and busy players, the request may succeed later
+fn is_transient_status(status: u16) -> bool {
+    matches!(status, 502..=504)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_transient, RetryPolicy};
+    use crate::error::Error;
+    use std::time::Duration;
+
+    fn http_status(status: u16) -> Error {
+        Error::HttpStatusError {
+            endpoint: "Status".to_string(),
+            status,
+            body: String::new(),
+        }
+    }
+
+    #[test]
+    fn backoff() {
+        let policy = RetryPolicy {
+            jitter: false,
+            ..RetryPolicy::default()
+        };
+        assert_eq!(policy.backoff(1), Duration::from_millis(250));
+        assert_eq!(policy.backoff(2), Duration::from_millis(500));
+        assert_eq!(policy.backoff(5), Duration::from_secs(2));
+        assert_eq!(policy.backoff(100), Duration::from_secs(2));
+
+        let policy = RetryPolicy::default();
+        for retry in 1..10 {
+            let delay = policy.backoff(retry);
+            let max = policy
+                .max_backoff
+                .min(Duration::from_millis(250 << (retry - 1)));
+            assert!(delay >= max / 2 && delay <= max, "{:?}", delay);
+        }
+    }
+
+    #[test]
+    fn transient_errors() {
+        assert!(is_transient(&http_status(503)));
+        assert!(!is_transient(&http_status(404)));
+        assert!(!is_transient(&Error::DeviceError {
+            endpoint: "Browse".to_string(),
+            status: 200,
+            message: "Invalid browse key".to_string(),
+            body: String::new(),
+        }));
+        assert!(is_transient(&Error::DeviceError {
+            endpoint: "Status".to_string(),
+            status: 503,
+            message: "Service unavailable".to_string(),
+            body: String::new(),
+        }));
+    }
+}
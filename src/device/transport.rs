@@ -0,0 +1,101 @@
+use super::PlayerAddress;
+use crate::error::Error;
+use futures::future::BoxFuture;
+use std::fmt;
+use std::time::Duration;
+
+/// A GET request for a BluOS player
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransportRequest {
+    /// Hostname or IP address of the player, IPv6 addresses are not bracketed
+    pub host: String,
+    pub port: u16,
+    /// Path and encoded query, e.g. `/Volume?level=20&tell_slaves=1`
+    pub path_and_query: String,
+    /// The request should fail with an error once this has passed
+    pub timeout: Duration,
+    pub user_agent: Option<String>,
+}
+
+impl TransportRequest {
+    /// The full URL of the request, e.g. `http://192.168.1.100:11000/Status`
+    pub fn url(&self) -> String {
+        format!(
+            "http://{}{}",
+            PlayerAddress::new(&self.host, self.port),
+            self.path_and_query
+        )
+    }
+}
+
+/// Status and body of the answer of a player
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransportResponse {
+    pub status: u16,
+    pub body: String,
+}
+
+/// Sends the requests of a [BluOS](crate::BluOS) client, see
+/// [BluOSBuilder::transport](crate::BluOSBuilder::transport)
+///
+/// Only answers of the player count as a response, HTTP errors included. Failures to get an
+/// answer, such as connection errors or timeouts, should be returned as
+/// [Error::TransportError], these are retried for requests that only read from the player.
+/// `ReqwestTransport` returns `Error::RequestError` instead so the reqwest error can be
+/// inspected, connection errors and timeouts among them are retried as well
+pub trait Transport: fmt::Debug + Send + Sync {
+    fn get(&self, request: TransportRequest) -> BoxFuture<'_, Result<TransportResponse, Error>>;
+}
+
+/// The default transport built on a [reqwest::Client]
+#[cfg(feature = "reqwest")]
+#[derive(Debug, Clone, Default)]
+pub struct ReqwestTransport {
+    client: reqwest::Client,
+}
+
+#[cfg(feature = "reqwest")]
+impl ReqwestTransport {
+    pub fn new(client: reqwest::Client) -> ReqwestTransport {
+        ReqwestTransport { client }
+    }
+}
+
+#[cfg(feature = "reqwest")]
+impl Transport for ReqwestTransport {
+    fn get(&self, request: TransportRequest) -> BoxFuture<'_, Result<TransportResponse, Error>> {
+        Box::pin(async move {
+            let mut builder = self.client.get(request.url()).timeout(request.timeout);
+            if let Some(user_agent) = &request.user_agent {
+                builder = builder.header(reqwest::header::USER_AGENT, user_agent);
+            }
+
+            let response = builder.send().await?;
+            let status = response.status().as_u16();
+            let body = response.text().await?;
+
+            Ok(TransportResponse { status, body })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TransportRequest;
+    use std::time::Duration;
+
+    #[test]
+    fn request_url() {
+        let mut request = TransportRequest {
+            host: "192.168.1.100".to_string(),
+            port: 11000,
+            path_and_query: "/Play?seek=10".to_string(),
+            timeout: Duration::from_secs(1),
+            user_agent: None,
+        };
+        assert_eq!(request.url(), "http://192.168.1.100:11000/Play?seek=10");
+
+        request.host = "fe80::1".to_string();
+        assert_eq!(request.url(), "http://[fe80::1]:11000/Play?seek=10");
+    }
+}
@@ -1,94 +1,312 @@
-#![cfg(feature = "discover")]
+#![cfg(any(feature = "discover", feature = "discover-mdns-sd", feature = "lsdp"))]
+#[cfg(feature = "lsdp")]
+mod lsdp;
+#[cfg(feature = "discover-mdns-sd")]
+mod mdns_sd_browser;
+mod registry;
+#[cfg(all(feature = "discover", not(feature = "discover-mdns-sd")))]
+mod zeroconf_browser;
+
+#[cfg(feature = "lsdp")]
+pub use lsdp::LsdpDiscovery;
+// The pure Rust backend wins if both mDNS backends are enabled
+#[cfg(feature = "discover-mdns-sd")]
+pub use mdns_sd_browser::Discovery;
+pub use registry::{DeviceRegistry, RegisteredPlayer, RegistryEvent};
+#[cfg(all(feature = "discover", not(feature = "discover-mdns-sd")))]
+pub use zeroconf_browser::Discovery;
+
 use crate::error::Error;
-use std::any::Any;
-use std::sync::Arc;
+use futures::channel::mpsc::{Receiver, SendError, Sender};
+use futures::future::{select, Either};
+use futures::{Future, SinkExt, StreamExt};
+use futures_timer::Delay;
+use if_addrs::IfAddr;
+use std::collections::HashMap;
+use std::net::IpAddr;
 use std::time::Duration;
-use tokio::sync::mpsc::{self, Receiver};
-use zeroconf::prelude::*;
-use zeroconf::{MdnsBrowser, ServiceDiscovery, ServiceType};
 
+/// mDNS service types announced by BluOS devices
+#[cfg(any(feature = "discover", feature = "discover-mdns-sd"))]
+const SERVICE_TYPES: &[(&str, DeviceKind)] = &[
+    ("_musc._tcp", DeviceKind::Player),
+    ("_musp._tcp", DeviceKind::SecondaryPlayer),
+    ("_musz._tcp", DeviceKind::Server),
+    ("_mush._tcp", DeviceKind::Hub),
+];
+
+#[derive(Debug, Clone)]
 pub struct DiscoveredBluOSDevice {
     pub name: String,
+    /// Address to connect to, IPv4 is preferred if the device has several
     pub hostname: String,
     pub port: u16,
+    /// Every address the device was resolved to. The zeroconf backend resolves each address
+    /// separately, so there it holds the single address of this result and a device with
+    /// several addresses is reported once per address
+    pub addresses: Vec<IpAddr>,
+    /// Name of the local network interface the device is reachable on, if it could be found
+    pub interface: Option<String>,
+    /// mDNS service type, e.g. `_musc._tcp`, or the LSDP class, e.g. `lsdp:0x0001`
+    pub service_type: String,
+    pub kind: DeviceKind,
+    /// TXT records of the announcement, such as the model and version of the device
+    pub txt: HashMap<String, String>,
 }
 
-pub struct Discovery {
-    cancel: Option<std::sync::mpsc::Sender<bool>>,
+/// The kind of BluOS service that was announced. Only players can be controlled with
+/// [BluOS](crate::BluOS)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DeviceKind {
+    Player,
+    /// Player that is part of a fixed group, e.g. the rear speakers of a home theater setup
+    SecondaryPlayer,
+    /// Second speaker of a stereo pair
+    PairSlave,
+    /// BluOS music server
+    Server,
+    Hub,
+    Other,
 }
 
-impl Discovery {
-    pub fn new() -> Discovery {
-        Discovery { cancel: None }
+impl DeviceKind {
+    #[cfg(any(feature = "discover", feature = "discover-mdns-sd"))]
+    fn from_service_type(service_type: &str) -> DeviceKind {
+        SERVICE_TYPES
+            .iter()
+            .find(|(t, _)| *t == service_type)
+            .map(|(_, kind)| *kind)
+            .unwrap_or(DeviceKind::Other)
     }
-    /// Discover uses mDNS to scan the network for BluOS devices
-    /// Returns a Tokio channel that streams results as they are found
-    ///
-    /// The discovery process is cancelled on drop
-    pub async fn discover(&mut self) -> Result<Receiver<DiscoveredBluOSDevice>, Error> {
-        //Check if we're already doing this
-        if self.cancel.is_some() {
-            return Err(Error::AlreadyDiscovering);
+}
+
+/// Sorts the addresses with IPv4 first, the first one is used as hostname
+#[cfg(any(feature = "discover", feature = "discover-mdns-sd"))]
+fn sort_addresses(addresses: &mut [IpAddr]) {
+    addresses.sort_by_key(|a| (a.is_ipv6(), *a));
+}
+
+/// Finds the local interface whose network contains one of the addresses
+fn interface_of(addresses: &[IpAddr]) -> Option<String> {
+    let interfaces = if_addrs::get_if_addrs().ok()?;
+    addresses.iter().find_map(|addr| {
+        interfaces
+            .iter()
+            .find(|i| same_network(&i.addr, addr))
+            .map(|i| i.name.clone())
+    })
+}
+
+fn same_network(interface: &IfAddr, addr: &IpAddr) -> bool {
+    match (interface, addr) {
+        (IfAddr::V4(i), IpAddr::V4(a)) => {
+            let mask = u32::from(i.netmask);
+            u32::from(i.ip) & mask == u32::from(*a) & mask
+        }
+        (IfAddr::V6(i), IpAddr::V6(a)) => {
+            let mask = u128::from(i.netmask);
+            u128::from(i.ip) & mask == u128::from(*a) & mask
         }
+        _ => false,
+    }
+}
 
-        let (tx, rx) = mpsc::channel(200);
-        let (ctx, crx): (
-            std::sync::mpsc::Sender<bool>,
-            std::sync::mpsc::Receiver<bool>,
-        ) = std::sync::mpsc::channel();
-        self.cancel = Some(ctx);
-
-        tokio::task::spawn_blocking(move || {
-            let mut browser = MdnsBrowser::new(ServiceType::new("musc", "tcp").unwrap());
-
-            browser.set_service_discovered_callback(Box::new(
-                move |result: zeroconf::Result<ServiceDiscovery>,
-                      _context: Option<Arc<dyn Any>>| {
-                    let res = result.unwrap();
-                    let _ = tx.blocking_send(DiscoveredBluOSDevice {
-                        name: res.name().clone(),
-                        hostname: res.address().clone(),
-                        port: *res.port(),
-                    });
-                },
-            ));
+type DiscoveryReceiver = Receiver<Result<DiscoveredBluOSDevice, Error>>;
+type DiscoverySender = Sender<Result<DiscoveredBluOSDevice, Error>>;
 
-            let event_loop = browser.browse_services().unwrap();
+/// A discovery backend. The helpers below and the registry are written once against this,
+/// the backends only have to start scanning
+pub(crate) trait Backend: Default + Send {
+    /// Starts scanning, the scan stops once the backend is dropped
+    fn discover(&mut self) -> impl Future<Output = Result<DiscoveryReceiver, Error>> + Send;
+}
 
-            loop {
-                event_loop.poll(Duration::from_millis(500)).unwrap();
+/// Scans with a new backend until the first player shows up
+async fn discover_one<D: Backend>(timeout: Duration) -> Result<DiscoveredBluOSDevice, Error> {
+    let mut d = D::default();
+    let mut c = d.discover().await?;
 
-                match crx.try_recv() {
-                    Ok(_) => return,
-                    Err(e) => match e {
-                        std::sync::mpsc::TryRecvError::Empty => {}
-                        std::sync::mpsc::TryRecvError::Disconnected => return,
-                    },
-                }
-            }
-        });
+    first_device(&mut c, timeout, |device| device.kind == DeviceKind::Player).await
+}
 
-        Ok(rx)
-    }
-    /// Discover one is a helper function that scans the network and returns the FIRST BluOS device it finds.
-    /// This is useful if you only have one BluOS device.
-    pub async fn discover_one() -> Result<DiscoveredBluOSDevice, Error> {
-        let mut d = Discovery::new();
-        let mut c = d.discover().await?;
+/// Scans with a new backend until the timeout, collecting every device
+async fn discover_all<D: Backend>(timeout: Duration) -> Result<Vec<DiscoveredBluOSDevice>, Error> {
+    let mut d = D::default();
+    let mut c = d.discover().await?;
 
-        let m = c.recv().await.ok_or(Error::NoBluOSError)?;
+    all_devices(&mut c, timeout).await
+}
 
-        Ok(m)
+/// Scans with a new backend until the player with the given name shows up, ignoring case
+async fn discover_named<D: Backend>(
+    name: &str,
+    timeout: Duration,
+) -> Result<DiscoveredBluOSDevice, Error> {
+    let mut d = D::default();
+    let mut c = d.discover().await?;
+
+    first_device(&mut c, timeout, |device| {
+        device.kind == DeviceKind::Player && device.name.eq_ignore_ascii_case(name)
+    })
+    .await
+}
+
+/// Sends a result from a discovery thread, waiting while the channel is full.
+/// Fails once the receiver is dropped
+fn blocking_send(
+    tx: &mut DiscoverySender,
+    res: Result<DiscoveredBluOSDevice, Error>,
+) -> Result<(), SendError> {
+    futures::executor::block_on(tx.send(res))
+}
+
+/// Receives the next result, or None once the channel is closed or the deadline has passed
+async fn recv_before(
+    rx: &mut DiscoveryReceiver,
+    deadline: &mut Delay,
+) -> Option<Result<DiscoveredBluOSDevice, Error>> {
+    match select(rx.next(), deadline).await {
+        Either::Left((res, _)) => res,
+        Either::Right(_) => None,
     }
 }
 
-impl Drop for Discovery {
-    fn drop(&mut self) {
-        match &self.cancel {
-            Some(c) => {
-                let _ = c.send(true);
+/// Waits for the first device on a discovery channel that matches `filter`, skipping errors
+/// along the way. If none shows up before the timeout, the last error is returned
+async fn first_device(
+    rx: &mut DiscoveryReceiver,
+    timeout: Duration,
+    filter: impl Fn(&DiscoveredBluOSDevice) -> bool,
+) -> Result<DiscoveredBluOSDevice, Error> {
+    let mut deadline = Delay::new(timeout);
+    let mut last_error = None;
+    while let Some(res) = recv_before(rx, &mut deadline).await {
+        match res {
+            Ok(device) if filter(&device) => return Ok(device),
+            Ok(_) => {}
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(last_error.unwrap_or(Error::NoBluOSError))
+}
+
+/// Collects every device found before the timeout. Players with several interfaces, or that
+/// announce themselves again, show up more than once so only the first result for a name and
+/// kind is kept
+async fn all_devices(
+    rx: &mut DiscoveryReceiver,
+    timeout: Duration,
+) -> Result<Vec<DiscoveredBluOSDevice>, Error> {
+    let mut deadline = Delay::new(timeout);
+    let mut devices: Vec<DiscoveredBluOSDevice> = Vec::new();
+    let mut last_error = None;
+    while let Some(res) = recv_before(rx, &mut deadline).await {
+        match res {
+            Ok(device) => {
+                if !devices
+                    .iter()
+                    .any(|d| d.name == device.name && d.kind == device.kind)
+                {
+                    devices.push(device);
+                }
             }
-            None => {}
+            Err(e) => last_error = Some(e),
         }
     }
+
+    // Only fail if nothing was found at all
+    match last_error {
+        Some(e) if devices.is_empty() => Err(e),
+        _ => Ok(devices),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{all_devices, first_device, same_network, DeviceKind, DiscoveredBluOSDevice};
+    use crate::error::Error;
+    use futures::channel::mpsc;
+    use futures::executor::block_on;
+    use futures::SinkExt;
+    use if_addrs::{IfAddr, Ifv4Addr};
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::time::Duration;
+
+    fn device(name: &str, hostname: &str) -> DiscoveredBluOSDevice {
+        DiscoveredBluOSDevice {
+            name: name.to_string(),
+            hostname: hostname.to_string(),
+            port: 11000,
+            addresses: vec![hostname.parse().unwrap()],
+            interface: None,
+            service_type: "_musc._tcp".to_string(),
+            kind: DeviceKind::Player,
+            txt: Default::default(),
+        }
+    }
+
+    #[test]
+    fn networks() {
+        let addresses: Vec<IpAddr> = vec![
+            "10.0.0.2".parse().unwrap(),
+            "192.168.1.100".parse().unwrap(),
+            "fe80::1".parse().unwrap(),
+        ];
+
+        let interface = IfAddr::V4(Ifv4Addr {
+            ip: Ipv4Addr::new(192, 168, 1, 10),
+            netmask: Ipv4Addr::new(255, 255, 255, 0),
+            prefixlen: 24,
+            broadcast: None,
+        });
+        assert!(same_network(&interface, &addresses[1]));
+        assert!(!same_network(&interface, &addresses[0]));
+        assert!(!same_network(&interface, &addresses[2]));
+    }
+
+    // Discovery does not need Tokio, so these run on the executor of futures
+    #[test]
+    fn deduplicates_devices() {
+        block_on(async {
+            let (mut tx, mut rx) = mpsc::channel(10);
+            tx.send(Ok(device("Kitchen", "10.0.0.2"))).await.unwrap();
+            tx.send(Err(Error::NoBluOSError)).await.unwrap();
+            tx.send(Ok(device("Kitchen", "192.168.1.2"))).await.unwrap();
+            tx.send(Ok(device("Office", "10.0.0.3"))).await.unwrap();
+            let mut hub = device("Office", "10.0.0.3");
+            hub.kind = DeviceKind::Hub;
+            tx.send(Ok(hub)).await.unwrap();
+
+            // The sender is kept open, so this only returns at the timeout
+            let devices = all_devices(&mut rx, Duration::from_millis(50))
+                .await
+                .unwrap();
+            assert_eq!(devices.len(), 3);
+            assert_eq!(devices[0].hostname, "10.0.0.2");
+            assert_eq!(devices[1].name, "Office");
+            assert_eq!(devices[2].kind, DeviceKind::Hub);
+            drop(tx);
+        });
+    }
+
+    #[test]
+    fn first_matching_device() {
+        block_on(async {
+            let (mut tx, mut rx) = mpsc::channel(10);
+            tx.send(Ok(device("Office", "10.0.0.3"))).await.unwrap();
+            tx.send(Ok(device("Kitchen", "10.0.0.2"))).await.unwrap();
+
+            let kitchen = first_device(&mut rx, Duration::from_secs(5), |d| d.name == "Kitchen")
+                .await
+                .unwrap();
+            assert_eq!(kitchen.hostname, "10.0.0.2");
+
+            // Nothing else arrives, so this times out
+            assert!(matches!(
+                first_device(&mut rx, Duration::from_millis(50), |_| true).await,
+                Err(Error::NoBluOSError)
+            ));
+            drop(tx);
+        });
+    }
 }
@@ -0,0 +1,588 @@
+use super::{blocking_send, interface_of, Backend, DeviceKind, DiscoveredBluOSDevice};
+use crate::error::Error;
+use futures::channel::mpsc::{self, Receiver};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+// Lenbrook Service Discovery Protocol
+// BluOS devices announce themselves with UDP broadcasts on this port
+const LSDP_PORT: u16 = 11430;
+const MAGIC: &[u8; 4] = b"LSDP";
+const VERSION: u8 = 1;
+const HEADER_LENGTH: u8 = 6;
+
+const MESSAGE_ANNOUNCE: u8 = b'A';
+const MESSAGE_DELETE: u8 = b'D';
+const MESSAGE_QUERY: u8 = b'Q';
+const MESSAGE_QUERY_UNICAST: u8 = b'R';
+
+/// Class ids of the BluOS services
+const CLASS_PLAYER: u16 = 0x0001;
+const CLASS_SERVER: u16 = 0x0002;
+const CLASS_SECONDARY_PLAYER: u16 = 0x0003;
+const CLASS_PAIR_SLAVE: u16 = 0x0006;
+const CLASS_HUB: u16 = 0x0008;
+const CLASSES: &[(u16, DeviceKind)] = &[
+    (CLASS_PLAYER, DeviceKind::Player),
+    (CLASS_SERVER, DeviceKind::Server),
+    (CLASS_SECONDARY_PLAYER, DeviceKind::SecondaryPlayer),
+    (CLASS_PAIR_SLAVE, DeviceKind::PairSlave),
+    (CLASS_HUB, DeviceKind::Hub),
+];
+
+/// Queries are repeated with a growing interval up to this
+const MAX_QUERY_INTERVAL: Duration = Duration::from_secs(30);
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// Discovery stops after this many consecutive socket errors
+const MAX_RECEIVE_FAILURES: u32 = 5;
+
+pub struct LsdpDiscovery {
+    cancel: Option<std::sync::mpsc::Sender<bool>>,
+}
+
+impl LsdpDiscovery {
+    pub fn new() -> LsdpDiscovery {
+        LsdpDiscovery { cancel: None }
+    }
+
+    /// Discover uses LSDP broadcasts to scan the network for BluOS devices
+    /// Returns a channel that streams results as they are found, it works with any executor
+    ///
+    /// LSDP works on networks where mDNS is filtered. Socket errors, including queries that could
+    /// not be sent, are sent through the channel, which is closed if receiving keeps failing. The discovery process is cancelled on drop
+    pub async fn discover(
+        &mut self,
+    ) -> Result<Receiver<Result<DiscoveredBluOSDevice, Error>>, Error> {
+        //Check if we're already doing this
+        if self.cancel.is_some() {
+            return Err(Error::AlreadyDiscovering);
+        }
+
+        let socket = bind_socket()?;
+        let query = Packet {
+            messages: vec![Message::Query {
+                unicast: false,
+                classes: CLASSES.iter().map(|(class, _)| *class).collect(),
+            }],
+        }
+        .encode()?;
+
+        let (mut tx, rx) = mpsc::channel(200);
+        let (ctx, crx): (
+            std::sync::mpsc::Sender<bool>,
+            std::sync::mpsc::Receiver<bool>,
+        ) = std::sync::mpsc::channel();
+        self.cancel = Some(ctx);
+
+        std::thread::spawn(move || {
+            let broadcast = SocketAddr::from((Ipv4Addr::BROADCAST, LSDP_PORT));
+            let mut query_interval = Duration::from_secs(1);
+            let mut next_query = Instant::now();
+            let mut buf = [0u8; 1500];
+            let mut failures = 0;
+
+            loop {
+                if Instant::now() >= next_query {
+                    // Without a usable interface nobody will answer, report it instead of
+                    // staying silent until the caller gives up
+                    if let Err(e) = socket.send_to(&query, broadcast) {
+                        if blocking_send(&mut tx, Err(e.into())).is_err() {
+                            return;
+                        }
+                    }
+                    next_query = Instant::now() + query_interval;
+                    query_interval = (query_interval * 2).min(MAX_QUERY_INTERVAL);
+                }
+
+                match socket.recv_from(&mut buf) {
+                    Ok((n, from)) => {
+                        failures = 0;
+                        let packet = match Packet::decode(&buf[..n]) {
+                            Ok(packet) => packet,
+                            Err(_) => continue,
+                        };
+                        for mut device in packet.devices(from.ip()) {
+                            device.interface = interface_of(&device.addresses);
+                            if blocking_send(&mut tx, Ok(device)).is_err() {
+                                return;
+                            }
+                        }
+                    }
+                    Err(e) => match e.kind() {
+                        std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut => {}
+                        _ => {
+                            failures += 1;
+                            if blocking_send(&mut tx, Err(e.into())).is_err()
+                                || failures >= MAX_RECEIVE_FAILURES
+                            {
+                                return;
+                            }
+                            std::thread::sleep(POLL_INTERVAL);
+                        }
+                    },
+                }
+
+                match crx.try_recv() {
+                    Ok(_) => return,
+                    Err(e) => match e {
+                        std::sync::mpsc::TryRecvError::Empty => {}
+                        std::sync::mpsc::TryRecvError::Disconnected => return,
+                    },
+                }
+            }
+        });
+
+        Ok(rx)
+    }
+
+    /// Discover one is a helper function that scans the network and returns the FIRST BluOS player it finds.
+    /// This is useful if you only have one BluOS device.
+    ///
+    /// Errors reported while scanning are skipped, the last one is returned if no device is found
+    /// before the timeout
+    pub async fn discover_one(timeout: Duration) -> Result<DiscoveredBluOSDevice, Error> {
+        super::discover_one::<Self>(timeout).await
+    }
+
+    /// Scans the network until the timeout and returns every BluOS device found, once per name and kind.
+    /// Besides players this includes hubs, servers and secondary players
+    pub async fn discover_all(timeout: Duration) -> Result<Vec<DiscoveredBluOSDevice>, Error> {
+        super::discover_all::<Self>(timeout).await
+    }
+
+    /// Scans the network for the BluOS player with the given name, ignoring case
+    pub async fn discover_named(
+        name: &str,
+        timeout: Duration,
+    ) -> Result<DiscoveredBluOSDevice, Error> {
+        super::discover_named::<Self>(name, timeout).await
+    }
+}
+
+impl Default for LsdpDiscovery {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Backend for LsdpDiscovery {
+    async fn discover(&mut self) -> Result<Receiver<Result<DiscoveredBluOSDevice, Error>>, Error> {
+        LsdpDiscovery::discover(self).await
+    }
+}
+
+impl Drop for LsdpDiscovery {
+    fn drop(&mut self) {
+        if let Some(c) = &self.cancel {
+            let _ = c.send(true);
+        }
+    }
+}
+
+/// Bind the LSDP port, other applications such as the BluOS controller may be listening too
+fn bind_socket() -> Result<UdpSocket, Error> {
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_reuse_address(true)?;
+    socket.set_broadcast(true)?;
+    socket.set_read_timeout(Some(POLL_INTERVAL))?;
+    socket.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, LSDP_PORT)).into())?;
+
+    Ok(socket.into())
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Packet {
+    messages: Vec<Message>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Message {
+    /// Ask nodes of the classes to announce themselves
+    /// - unicast: If set, answers are sent to the querying node only
+    Query {
+        unicast: bool,
+        classes: Vec<u16>,
+    },
+    Announce(Announce),
+    /// A node stopped providing the classes
+    Delete {
+        node_id: Vec<u8>,
+        classes: Vec<u16>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Announce {
+    /// Unique id of the node, the MAC address for BluOS devices
+    node_id: Vec<u8>,
+    address: IpAddr,
+    records: Vec<Record>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Record {
+    class: u16,
+    txt: Vec<(String, String)>,
+}
+
+impl Record {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.txt
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+impl Packet {
+    fn decode(data: &[u8]) -> Result<Packet, Error> {
+        let mut r = Reader::new(data);
+
+        let header_length = r.u8()?;
+        if header_length < HEADER_LENGTH {
+            return Err(Error::LsdpError("header too short"));
+        }
+        if r.bytes(4)? != MAGIC {
+            return Err(Error::LsdpError("bad magic word"));
+        }
+        if r.u8()? != VERSION {
+            return Err(Error::LsdpError("unsupported version"));
+        }
+        r.bytes((header_length - HEADER_LENGTH) as usize)?;
+
+        let mut messages = Vec::new();
+        while !r.is_empty() {
+            let length = r.u8()? as usize;
+            if length < 2 {
+                return Err(Error::LsdpError("message too short"));
+            }
+            let mut m = Reader::new(r.bytes(length - 1)?);
+            let message = match m.u8()? {
+                MESSAGE_QUERY | MESSAGE_QUERY_UNICAST => Message::Query {
+                    unicast: m.data[0] == MESSAGE_QUERY_UNICAST,
+                    classes: m.classes()?,
+                },
+                MESSAGE_ANNOUNCE => Message::Announce(Announce::decode(&mut m)?),
+                MESSAGE_DELETE => Message::Delete {
+                    node_id: m.field()?.to_vec(),
+                    classes: m.classes()?,
+                },
+                // Unknown messages are skipped, the length tells us where the next one starts
+                _ => continue,
+            };
+            messages.push(message);
+        }
+
+        Ok(Packet { messages })
+    }
+
+    fn encode(&self) -> Result<Vec<u8>, Error> {
+        let mut data = vec![HEADER_LENGTH];
+        data.extend_from_slice(MAGIC);
+        data.push(VERSION);
+
+        for message in &self.messages {
+            let mut w = Vec::new();
+            match message {
+                Message::Query { unicast, classes } => {
+                    w.push(if *unicast {
+                        MESSAGE_QUERY_UNICAST
+                    } else {
+                        MESSAGE_QUERY
+                    });
+                    write_classes(&mut w, classes)?;
+                }
+                Message::Announce(announce) => {
+                    w.push(MESSAGE_ANNOUNCE);
+                    announce.encode(&mut w)?;
+                }
+                Message::Delete { node_id, classes } => {
+                    w.push(MESSAGE_DELETE);
+                    write_field(&mut w, node_id)?;
+                    write_classes(&mut w, classes)?;
+                }
+            }
+            data.push(length_byte(w.len() + 1)?);
+            data.extend_from_slice(&w);
+        }
+
+        Ok(data)
+    }
+
+    /// The BluOS services announced in the packet, records of unknown classes are skipped
+    ///
+    /// Falls back to the address the packet was received from if the announcement has none
+    fn devices(&self, from: IpAddr) -> Vec<DiscoveredBluOSDevice> {
+        let mut devices = Vec::new();
+        for message in &self.messages {
+            let announce = match message {
+                Message::Announce(a) => a,
+                _ => continue,
+            };
+            let address = match announce.address.is_unspecified() {
+                true => from,
+                false => announce.address,
+            };
+
+            for record in &announce.records {
+                let kind = match CLASSES.iter().find(|(class, _)| *class == record.class) {
+                    Some((_, kind)) => *kind,
+                    None => continue,
+                };
+                devices.push(DiscoveredBluOSDevice {
+                    name: record.get("name").unwrap_or_default().to_string(),
+                    hostname: address.to_string(),
+                    port: record
+                        .get("port")
+                        .and_then(|p| p.parse().ok())
+                        .unwrap_or(11000),
+                    addresses: vec![address],
+                    interface: None,
+                    service_type: format!("lsdp:{:#06x}", record.class),
+                    kind,
+                    txt: record.txt.iter().cloned().collect(),
+                });
+            }
+        }
+        devices
+    }
+}
+
+impl Announce {
+    fn decode(r: &mut Reader) -> Result<Announce, Error> {
+        let node_id = r.field()?.to_vec();
+        let address = match r.field()? {
+            [a, b, c, d] => IpAddr::from([*a, *b, *c, *d]),
+            a if a.len() == 16 => {
+                let mut octets = [0u8; 16];
+                octets.copy_from_slice(a);
+                IpAddr::from(Ipv6Addr::from(octets))
+            }
+            _ => return Err(Error::LsdpError("bad address length")),
+        };
+
+        let count = r.u8()?;
+        let mut records = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let class = r.u16()?;
+            let txt_count = r.u8()?;
+            let mut txt = Vec::with_capacity(txt_count as usize);
+            for _ in 0..txt_count {
+                let key = String::from_utf8_lossy(r.field()?).into_owned();
+                let value = String::from_utf8_lossy(r.field()?).into_owned();
+                txt.push((key, value));
+            }
+            records.push(Record { class, txt });
+        }
+
+        Ok(Announce {
+            node_id,
+            address,
+            records,
+        })
+    }
+
+    fn encode(&self, w: &mut Vec<u8>) -> Result<(), Error> {
+        write_field(w, &self.node_id)?;
+        match self.address {
+            IpAddr::V4(a) => write_field(w, &a.octets())?,
+            IpAddr::V6(a) => write_field(w, &a.octets())?,
+        }
+        w.push(length_byte(self.records.len())?);
+        for record in &self.records {
+            w.extend_from_slice(&record.class.to_be_bytes());
+            w.push(length_byte(record.txt.len())?);
+            for (key, value) in &record.txt {
+                write_field(w, key.as_bytes())?;
+                write_field(w, value.as_bytes())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn length_byte(length: usize) -> Result<u8, Error> {
+    u8::try_from(length).map_err(|_| Error::LsdpError("field too long"))
+}
+
+/// Fields are prefixed with their length as a single byte
+fn write_field(w: &mut Vec<u8>, field: &[u8]) -> Result<(), Error> {
+    w.push(length_byte(field.len())?);
+    w.extend_from_slice(field);
+    Ok(())
+}
+
+fn write_classes(w: &mut Vec<u8>, classes: &[u16]) -> Result<(), Error> {
+    w.push(length_byte(classes.len())?);
+    for class in classes {
+        w.extend_from_slice(&class.to_be_bytes());
+    }
+    Ok(())
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Reader<'a> {
+        Reader { data, pos: 0 }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], Error> {
+        let end = self.pos + n;
+        let bytes = self
+            .data
+            .get(self.pos..end)
+            .ok_or(Error::LsdpError("packet truncated"))?;
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, Error> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn field(&mut self) -> Result<&'a [u8], Error> {
+        let length = self.u8()? as usize;
+        self.bytes(length)
+    }
+
+    fn classes(&mut self) -> Result<Vec<u16>, Error> {
+        let count = self.u8()?;
+        (0..count).map(|_| self.u16()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Announce, Message, Packet, Record, CLASS_HUB, CLASS_PLAYER};
+    use crate::discover::DeviceKind;
+    use std::net::{IpAddr, Ipv4Addr};
+
+    /// Announcement with a player and a secondary record, built by hand from the LSDP
+    /// specification with the fields a NODE 2i sends. It is not a capture of a player
+    const ANNOUNCE: &[u8] = &[
+        0x06, 0x4c, 0x53, 0x44, 0x50, 0x01, 0x5b, 0x41, 0x06, 0x90, 0x56, 0x82, 0x12, 0x34, 0x56,
+        0x04, 0xc0, 0xa8, 0x01, 0x64, 0x02, 0x00, 0x01, 0x04, 0x04, 0x6e, 0x61, 0x6d, 0x65, 0x0b,
+        0x4c, 0x69, 0x76, 0x69, 0x6e, 0x67, 0x20, 0x52, 0x6f, 0x6f, 0x6d, 0x04, 0x70, 0x6f, 0x72,
+        0x74, 0x05, 0x31, 0x31, 0x30, 0x30, 0x30, 0x05, 0x6d, 0x6f, 0x64, 0x65, 0x6c, 0x04, 0x4e,
+        0x31, 0x33, 0x30, 0x07, 0x76, 0x65, 0x72, 0x73, 0x69, 0x6f, 0x6e, 0x05, 0x34, 0x2e, 0x32,
+        0x2e, 0x38, 0x00, 0x04, 0x01, 0x04, 0x6e, 0x61, 0x6d, 0x65, 0x0b, 0x4c, 0x69, 0x76, 0x69,
+        0x6e, 0x67, 0x20, 0x52, 0x6f, 0x6f, 0x6d,
+    ];
+
+    /// Broadcast query for BluOS players
+    const QUERY: &[u8] = &[
+        0x06, 0x4c, 0x53, 0x44, 0x50, 0x01, 0x05, 0x51, 0x01, 0x00, 0x01,
+    ];
+
+    fn txt(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn decode_announce() {
+        let packet = Packet::decode(ANNOUNCE).unwrap();
+        assert_eq!(
+            packet,
+            Packet {
+                messages: vec![Message::Announce(Announce {
+                    node_id: vec![0x90, 0x56, 0x82, 0x12, 0x34, 0x56],
+                    address: IpAddr::V4(Ipv4Addr::new(192, 168, 1, 100)),
+                    records: vec![
+                        Record {
+                            class: CLASS_PLAYER,
+                            txt: txt(&[
+                                ("name", "Living Room"),
+                                ("port", "11000"),
+                                ("model", "N130"),
+                                ("version", "4.2.8"),
+                            ]),
+                        },
+                        Record {
+                            class: 0x0004,
+                            txt: txt(&[("name", "Living Room")]),
+                        },
+                    ],
+                })],
+            }
+        );
+
+        let devices = packet.devices(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+        assert_eq!(devices.len(), 1);
+        assert_eq!(devices[0].name, "Living Room");
+        assert_eq!(devices[0].hostname, "192.168.1.100");
+        assert_eq!(devices[0].port, 11000);
+        assert_eq!(devices[0].kind, DeviceKind::Player);
+        assert_eq!(devices[0].service_type, "lsdp:0x0001");
+        assert_eq!(devices[0].txt["model"], "N130");
+    }
+
+    #[test]
+    fn device_kinds() {
+        let from = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 101));
+        let packet = Packet {
+            messages: vec![Message::Announce(Announce {
+                node_id: vec![0x90, 0x56, 0x82, 0x12, 0x34, 0x57],
+                address: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+                records: vec![Record {
+                    class: CLASS_HUB,
+                    txt: txt(&[("name", "Hub"), ("port", "11010")]),
+                }],
+            })],
+        };
+
+        let devices = packet.devices(from);
+        assert_eq!(devices[0].kind, DeviceKind::Hub);
+        assert_eq!(devices[0].port, 11010);
+        assert_eq!(devices[0].addresses, vec![from]);
+    }
+
+    #[test]
+    fn roundtrip() {
+        assert_eq!(
+            Packet::decode(ANNOUNCE).unwrap().encode().unwrap(),
+            ANNOUNCE
+        );
+
+        let query = Packet {
+            messages: vec![Message::Query {
+                unicast: false,
+                classes: vec![CLASS_PLAYER],
+            }],
+        };
+        assert_eq!(query.encode().unwrap(), QUERY);
+        assert_eq!(Packet::decode(QUERY).unwrap(), query);
+    }
+
+    #[test]
+    fn invalid_packets() {
+        assert!(Packet::decode(&[]).is_err());
+        assert!(Packet::decode(b"\x06LSDQ\x01").is_err());
+        // Truncated in the middle of the announcement
+        assert!(Packet::decode(&ANNOUNCE[..40]).is_err());
+    }
+
+    #[test]
+    fn skip_unknown_messages() {
+        let mut data = b"\x06LSDP\x01\x04Zab".to_vec();
+        data.extend_from_slice(&QUERY[6..]);
+        let packet = Packet::decode(&data).unwrap();
+        assert_eq!(packet.messages.len(), 1);
+    }
+}
@@ -0,0 +1,183 @@
+use super::{
+    blocking_send, interface_of, sort_addresses, Backend, DeviceKind, DiscoveredBluOSDevice,
+    SERVICE_TYPES,
+};
+use crate::error::Error;
+use futures::channel::mpsc::{self, Receiver};
+use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
+use std::net::IpAddr;
+use std::time::Duration;
+
+const DOMAIN: &str = "local.";
+
+/// mDNS discovery built on mdns-sd, a pure Rust implementation that
+/// does not need Avahi or Bonjour
+pub struct Discovery {
+    daemon: Option<ServiceDaemon>,
+}
+
+impl Discovery {
+    pub fn new() -> Discovery {
+        Discovery { daemon: None }
+    }
+    /// Discover uses mDNS to scan the network for BluOS devices
+    /// Returns a channel that streams results as they are found, it works with any executor
+    ///
+    /// The discovery process is cancelled on drop
+    pub async fn discover(
+        &mut self,
+    ) -> Result<Receiver<Result<DiscoveredBluOSDevice, Error>>, Error> {
+        //Check if we're already doing this
+        if self.daemon.is_some() {
+            return Err(Error::AlreadyDiscovering);
+        }
+
+        let daemon = ServiceDaemon::new()?;
+        let (tx, rx) = mpsc::channel(200);
+        for (service_type, _) in SERVICE_TYPES {
+            let events = daemon.browse(&format!("{}.{}", service_type, DOMAIN))?;
+            let mut tx = tx.clone();
+            std::thread::spawn(move || {
+                // The receiver is closed once the daemon shuts down
+                while let Ok(event) = events.recv() {
+                    if let ServiceEvent::ServiceResolved(info) = event {
+                        let mut device = discovered(&info);
+                        device.interface = interface_of(&device.addresses);
+                        if blocking_send(&mut tx, Ok(device)).is_err() {
+                            return;
+                        }
+                    }
+                }
+            });
+        }
+        self.daemon = Some(daemon);
+
+        Ok(rx)
+    }
+    /// Discover one is a helper function that scans the network and returns the FIRST BluOS player it finds.
+    /// This is useful if you only have one BluOS device.
+    ///
+    /// Errors reported while scanning are skipped, the last one is returned if no device is found
+    /// before the timeout
+    pub async fn discover_one(timeout: Duration) -> Result<DiscoveredBluOSDevice, Error> {
+        super::discover_one::<Self>(timeout).await
+    }
+
+    /// Scans the network until the timeout and returns every BluOS device found, once per name and kind.
+    /// Besides players this includes hubs, servers and secondary players
+    pub async fn discover_all(timeout: Duration) -> Result<Vec<DiscoveredBluOSDevice>, Error> {
+        super::discover_all::<Self>(timeout).await
+    }
+
+    /// Scans the network for the BluOS player with the given name, ignoring case
+    pub async fn discover_named(
+        name: &str,
+        timeout: Duration,
+    ) -> Result<DiscoveredBluOSDevice, Error> {
+        super::discover_named::<Self>(name, timeout).await
+    }
+}
+
+fn discovered(info: &ServiceInfo) -> DiscoveredBluOSDevice {
+    let service_type = info
+        .get_type()
+        .strip_suffix(DOMAIN)
+        .unwrap_or(info.get_type())
+        .trim_end_matches('.');
+    let name = info
+        .get_fullname()
+        .strip_suffix(info.get_type())
+        .map(|n| n.trim_end_matches('.'))
+        .unwrap_or(info.get_fullname());
+
+    let mut addresses: Vec<IpAddr> = info.get_addresses().iter().copied().collect();
+    sort_addresses(&mut addresses);
+
+    // Fall back to the mDNS hostname if no address was resolved
+    let hostname = match addresses.first() {
+        Some(addr) => addr.to_string(),
+        None => info.get_hostname().trim_end_matches('.').to_string(),
+    };
+
+    DiscoveredBluOSDevice {
+        name: name.to_string(),
+        hostname,
+        port: info.get_port(),
+        addresses,
+        interface: None,
+        service_type: service_type.to_string(),
+        kind: DeviceKind::from_service_type(service_type),
+        txt: info
+            .get_properties()
+            .iter()
+            .map(|p| (p.key().to_string(), p.val_str().to_string()))
+            .collect(),
+    }
+}
+
+impl Default for Discovery {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Backend for Discovery {
+    async fn discover(&mut self) -> Result<Receiver<Result<DiscoveredBluOSDevice, Error>>, Error> {
+        Discovery::discover(self).await
+    }
+}
+
+impl Drop for Discovery {
+    fn drop(&mut self) {
+        if let Some(d) = &self.daemon {
+            let _ = d.shutdown();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::discovered;
+    use crate::discover::DeviceKind;
+    use mdns_sd::ServiceInfo;
+
+    #[test]
+    fn resolved_service() {
+        let info = ServiceInfo::new(
+            "_musc._tcp.local.",
+            "Living Room",
+            "living-room.local.",
+            "fe80::1,192.168.1.100",
+            11000,
+            &[("model", "N130"), ("version", "4.2.8")][..],
+        )
+        .unwrap();
+
+        let device = discovered(&info);
+        assert_eq!(device.name, "Living Room");
+        assert_eq!(device.hostname, "192.168.1.100");
+        assert_eq!(device.port, 11000);
+        assert_eq!(device.addresses.len(), 2);
+        assert_eq!(device.service_type, "_musc._tcp");
+        assert_eq!(device.kind, DeviceKind::Player);
+        assert_eq!(device.txt["model"], "N130");
+    }
+
+    #[test]
+    fn resolved_hub() {
+        let info = ServiceInfo::new(
+            "_mush._tcp.local.",
+            "Hub",
+            "hub.local.",
+            "192.168.1.101",
+            11000,
+            None,
+        )
+        .unwrap();
+
+        let device = discovered(&info);
+        assert_eq!(device.name, "Hub");
+        assert_eq!(device.kind, DeviceKind::Hub);
+        assert!(device.txt.is_empty());
+    }
+}
@@ -0,0 +1,575 @@
+#[cfg(any(feature = "discover", feature = "discover-mdns-sd"))]
+use super::Discovery;
+#[cfg(feature = "lsdp")]
+use super::LsdpDiscovery;
+use super::{Backend, DeviceKind, DiscoveredBluOSDevice};
+use crate::device::{BluOS, BluOSBuilder, PlayerAddress, RetryPolicy};
+use crate::error::Error;
+use futures::channel::mpsc::{self, Receiver, Sender};
+use futures::future::{abortable, join_all, AbortHandle, BoxFuture};
+use futures::{stream, Future, SinkExt, StreamExt};
+use futures_timer::Delay;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// How often known players are checked, unless another interval is given
+const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+/// A player is removed after failing this many checks in a row
+const MAX_MISSED_CHECKS: u32 = 3;
+/// Discovery backends that stop are started again after this delay
+const RESTART_DELAY: Duration = Duration::from_secs(5);
+/// Identifying an announced player gives up after this long, without retries
+const IDENTIFY_TIMEOUT: Duration = Duration::from_secs(3);
+/// Announcements and checks handled at the same time
+const MAX_CONCURRENT: usize = 16;
+
+type Discovered = Result<DiscoveredBluOSDevice, Error>;
+type Players = Arc<Mutex<HashMap<String, RegisteredPlayer>>>;
+type Spawn = Box<dyn Fn(BoxFuture<'static, ()>) + Send + Sync>;
+
+/// Changes to the players known by a [DeviceRegistry]
+#[derive(Debug, Clone)]
+pub enum RegistryEvent {
+    Added(RegisteredPlayer),
+    /// The player stopped answering
+    Removed(RegisteredPlayer),
+    AddressChanged {
+        mac: String,
+        from: PlayerAddress,
+        to: PlayerAddress,
+    },
+    Renamed {
+        mac: String,
+        from: String,
+        to: String,
+    },
+    /// A discovery backend reported an error, for example because no network is available.
+    /// Discovery goes on and backends that stopped are started again
+    DiscoveryFailed(Arc<Error>),
+}
+
+/// A player known by a [DeviceRegistry]
+#[derive(Debug, Clone)]
+pub struct RegisteredPlayer {
+    /// MAC address reported by /SyncStatus, in upper case. Stays the same if the player is
+    /// re-addressed or renamed
+    pub mac: String,
+    pub name: String,
+    pub address: PlayerAddress,
+    /// The latest announcement of the player
+    pub discovered: DiscoveredBluOSDevice,
+    bluos: Arc<BluOS>,
+    missed_checks: u32,
+}
+
+impl RegisteredPlayer {
+    /// Handle for controlling the player at its current address
+    pub fn bluos(&self) -> Arc<BluOS> {
+        self.bluos.clone()
+    }
+}
+
+/// Keeps track of the BluOS players on the network
+///
+/// Discovery runs continuously and every player found is identified by the MAC address in its
+/// /SyncStatus. Known players are checked periodically and removed once they stop answering.
+/// Everything stops when the registry is dropped
+///
+/// With the `tokio` feature the registry runs on the Tokio runtime it is started from, other
+/// executors can be used with [spawn_with](DeviceRegistry::spawn_with). The default transport
+/// needs Tokio, so on other executors also set a transport with
+/// [player_builder](DeviceRegistry::player_builder)
+pub struct DeviceRegistry {
+    players: Players,
+    check_interval: Duration,
+    builder: BluOSBuilder,
+    spawn: Option<Spawn>,
+    tasks: Vec<AbortHandle>,
+}
+
+impl DeviceRegistry {
+    pub fn new() -> DeviceRegistry {
+        DeviceRegistry::with_check_interval(CHECK_INTERVAL)
+    }
+
+    /// Check known players at the given interval instead of every 30 seconds
+    pub fn with_check_interval(check_interval: Duration) -> DeviceRegistry {
+        DeviceRegistry {
+            players: Default::default(),
+            check_interval,
+            builder: BluOSBuilder::new(),
+            spawn: None,
+            tasks: Vec::new(),
+        }
+    }
+
+    /// Run the background tasks on another executor, for example with smol:
+    /// `registry.spawn_with(|task| smol::spawn(task).detach())`
+    pub fn spawn_with(
+        mut self,
+        spawn: impl Fn(BoxFuture<'static, ()>) + Send + Sync + 'static,
+    ) -> DeviceRegistry {
+        self.spawn = Some(Box::new(spawn));
+        self
+    }
+
+    /// Create the clients of the players with these settings, for example to send requests
+    /// with another [Transport](crate::Transport). The address is the one of the announcement
+    pub fn player_builder(mut self, builder: BluOSBuilder) -> DeviceRegistry {
+        self.builder = builder;
+        self
+    }
+
+    /// Start discovering with every enabled discovery backend
+    /// Returns a channel that streams changes to the known players
+    ///
+    /// Fails with [NoExecutorError](Error::NoExecutorError) if no executor was set with
+    /// [spawn_with](DeviceRegistry::spawn_with) and this is not called from a Tokio runtime
+    ///
+    /// Backends that stop, for example because the network went away, are started again.
+    /// Their errors are sent as [DiscoveryFailed](RegistryEvent::DiscoveryFailed)
+    pub fn start(&mut self) -> Result<Receiver<RegistryEvent>, Error> {
+        self.check_startable()?;
+
+        let (tx, rx) = mpsc::channel(200);
+        #[cfg(any(feature = "discover", feature = "discover-mdns-sd"))]
+        self.spawn_task(run_backend::<Discovery>(tx.clone()));
+        #[cfg(feature = "lsdp")]
+        self.spawn_task(run_backend::<LsdpDiscovery>(tx.clone()));
+
+        Ok(self.spawn_run(rx))
+    }
+
+    /// Like [start](DeviceRegistry::start), but with devices from another source such as
+    /// the channel of a single discovery backend
+    pub fn start_with(
+        &mut self,
+        discovered: Receiver<Discovered>,
+    ) -> Result<Receiver<RegistryEvent>, Error> {
+        self.check_startable()?;
+
+        Ok(self.spawn_run(discovered))
+    }
+
+    fn check_startable(&mut self) -> Result<(), Error> {
+        if !self.tasks.is_empty() {
+            return Err(Error::AlreadyDiscovering);
+        }
+        if self.spawn.is_none() {
+            self.spawn = Some(current_runtime()?);
+        }
+        Ok(())
+    }
+
+    fn spawn_run(&mut self, discovered: Receiver<Discovered>) -> Receiver<RegistryEvent> {
+        let (tx, rx) = mpsc::channel(200);
+        self.spawn_task(run(
+            self.players.clone(),
+            discovered,
+            tx,
+            self.check_interval,
+            self.builder.clone(),
+        ));
+        rx
+    }
+
+    fn spawn_task(&mut self, task: impl Future<Output = ()> + Send + 'static) {
+        let (task, handle) = abortable(task);
+        if let Some(spawn) = &self.spawn {
+            spawn(Box::pin(async {
+                let _ = task.await;
+            }));
+            self.tasks.push(handle);
+        }
+    }
+
+    /// All currently known players
+    pub fn players(&self) -> Vec<RegisteredPlayer> {
+        self.players.lock().unwrap().values().cloned().collect()
+    }
+
+    /// Look up a player by MAC address, ignoring case
+    pub fn get(&self, mac: &str) -> Option<RegisteredPlayer> {
+        self.players
+            .lock()
+            .unwrap()
+            .get(&mac.to_uppercase())
+            .cloned()
+    }
+
+    /// Handle for the player with the given MAC address
+    pub fn player(&self, mac: &str) -> Option<Arc<BluOS>> {
+        self.get(mac).map(|p| p.bluos)
+    }
+
+    /// Handle for the player with the given name, ignoring case
+    pub fn player_named(&self, name: &str) -> Option<Arc<BluOS>> {
+        self.players
+            .lock()
+            .unwrap()
+            .values()
+            .find(|p| p.name.eq_ignore_ascii_case(name))
+            .map(|p| p.bluos())
+    }
+}
+
+impl Default for DeviceRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for DeviceRegistry {
+    fn drop(&mut self) {
+        // Dropping the discovery handles inside the tasks cancels them
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+/// Spawns on the Tokio runtime the registry was started from
+#[cfg(feature = "tokio")]
+fn current_runtime() -> Result<Spawn, Error> {
+    let handle = tokio::runtime::Handle::try_current().map_err(|_| Error::NoExecutorError)?;
+    Ok(Box::new(move |task| {
+        handle.spawn(task);
+    }))
+}
+
+#[cfg(not(feature = "tokio"))]
+fn current_runtime() -> Result<Spawn, Error> {
+    Err(Error::NoExecutorError)
+}
+
+/// Keeps a discovery backend running, restarting it whenever it stops
+async fn run_backend<D: Backend>(mut tx: Sender<Discovered>) {
+    loop {
+        let mut discovery = D::default();
+        if !forward(discovery.discover().await, &mut tx).await {
+            return;
+        }
+        Delay::new(RESTART_DELAY).await;
+    }
+}
+
+/// Forwards results until the backend stops. Returns false once nobody is listening anymore
+async fn forward(rx: Result<Receiver<Discovered>, Error>, tx: &mut Sender<Discovered>) -> bool {
+    let mut rx = match rx {
+        Ok(rx) => rx,
+        Err(e) => return tx.send(Err(e)).await.is_ok(),
+    };
+    while let Some(res) = rx.next().await {
+        if tx.send(res).await.is_err() {
+            return false;
+        }
+    }
+    true
+}
+
+enum Input {
+    Discovered(Discovered),
+    Check,
+}
+
+async fn run(
+    players: Players,
+    discovered: Receiver<Discovered>,
+    mut events: Sender<RegistryEvent>,
+    check_interval: Duration,
+    builder: BluOSBuilder,
+) {
+    // Keeps ticking if the source of devices closes, so known players are still checked
+    let checks = stream::unfold((), move |_| async move {
+        Delay::new(check_interval).await;
+        Some((Input::Check, ()))
+    });
+    let checking = Arc::new(AtomicBool::new(false));
+
+    // Announcements and checks are handled concurrently so a player that doesn't answer
+    // only holds up itself
+    let changes = stream::select(discovered.map(Input::Discovered), checks)
+        .map(|input| {
+            let players = players.clone();
+            let checking = checking.clone();
+            let builder = builder.clone();
+            async move {
+                match input {
+                    Input::Discovered(Ok(device)) => observe(&players, device, builder).await,
+                    Input::Discovered(Err(e)) => vec![RegistryEvent::DiscoveryFailed(Arc::new(e))],
+                    // Skipped while the previous check still waits for players
+                    Input::Check if checking.swap(true, Ordering::AcqRel) => Vec::new(),
+                    Input::Check => {
+                        let changes = check_players(&players).await;
+                        checking.store(false, Ordering::Release);
+                        changes
+                    }
+                }
+            }
+        })
+        .buffer_unordered(MAX_CONCURRENT);
+    futures::pin_mut!(changes);
+
+    while let Some(changes) = changes.next().await {
+        for change in changes {
+            // Nobody listening is fine, the registry can still be queried
+            let _ = events.send(change).await;
+        }
+    }
+}
+
+/// Identifies a discovered player and adds or updates it
+async fn observe(
+    players: &Players,
+    device: DiscoveredBluOSDevice,
+    builder: BluOSBuilder,
+) -> Vec<RegistryEvent> {
+    if device.kind != DeviceKind::Player {
+        return Vec::new();
+    }
+
+    // Players answering the checks at the announced address don't need to be identified again
+    {
+        let mut players = players.lock().unwrap();
+        let known = players
+            .values_mut()
+            .find(|p| p.missed_checks == 0 && is_announced_at(&p.address, &device));
+        if let Some(player) = known {
+            player.discovered = device;
+            return Vec::new();
+        }
+    }
+
+    let builder = builder.hostname(&device.hostname).port(device.port);
+    let probe = builder
+        .clone()
+        .timeout(IDENTIFY_TIMEOUT)
+        .retry_policy(RetryPolicy::none())
+        .build();
+    let (mac, name) = match probe {
+        Ok(probe) => match probe.sync_status().await {
+            Ok(sync_status) => match sync_status.mac {
+                Some(mac) => (mac.to_uppercase(), sync_status.name),
+                None => return Vec::new(),
+            },
+            Err(_) => return Vec::new(),
+        },
+        Err(_) => return Vec::new(),
+    };
+    let bluos = match builder.build() {
+        Ok(bluos) => Arc::new(bluos),
+        Err(_) => return Vec::new(),
+    };
+
+    let address = PlayerAddress::new(&device.hostname, device.port);
+    let mut players = players.lock().unwrap();
+    let player = match players.get_mut(&mac) {
+        Some(player) => player,
+        None => {
+            let player = RegisteredPlayer {
+                mac: mac.clone(),
+                name,
+                address,
+                discovered: device,
+                bluos,
+                missed_checks: 0,
+            };
+            players.insert(mac, player.clone());
+            return vec![RegistryEvent::Added(player)];
+        }
+    };
+
+    let mut changes = Vec::new();
+    if !is_announced_at(&player.address, &device) {
+        changes.push(RegistryEvent::AddressChanged {
+            mac: mac.clone(),
+            from: std::mem::replace(&mut player.address, address.clone()),
+            to: address,
+        });
+        player.bluos = bluos;
+    }
+    if player.name != name {
+        changes.push(RegistryEvent::Renamed {
+            mac,
+            from: std::mem::replace(&mut player.name, name.clone()),
+            to: name,
+        });
+    }
+    player.discovered = device;
+    player.missed_checks = 0;
+    changes
+}
+
+/// Whether the announcement is for the given address. Players with several network
+/// interfaces are announced with any of their addresses
+fn is_announced_at(address: &PlayerAddress, device: &DiscoveredBluOSDevice) -> bool {
+    address.port == device.port
+        && (address.host == device.hostname
+            || device
+                .addresses
+                .iter()
+                .any(|addr| addr.to_string() == address.host))
+}
+
+/// Checks that the known players still answer at their address
+async fn check_players(players: &Players) -> Vec<RegistryEvent> {
+    let known: Vec<RegisteredPlayer> = players.lock().unwrap().values().cloned().collect();
+    let results = join_all(known.iter().map(|p| p.bluos.sync_status())).await;
+
+    let mut players = players.lock().unwrap();
+    let mut changes = Vec::new();
+    for (known, res) in known.iter().zip(results) {
+        let player = match players.get_mut(&known.mac) {
+            Some(player) => player,
+            None => continue,
+        };
+
+        match res {
+            // Another player may have taken over the address
+            Ok(sync_status)
+                if sync_status.mac.as_deref().map(str::to_uppercase).as_ref()
+                    == Some(&player.mac) =>
+            {
+                player.missed_checks = 0;
+                if player.name != sync_status.name {
+                    changes.push(RegistryEvent::Renamed {
+                        mac: player.mac.clone(),
+                        from: std::mem::replace(&mut player.name, sync_status.name.clone()),
+                        to: sync_status.name,
+                    });
+                }
+            }
+            _ => {
+                player.missed_checks += 1;
+                if player.missed_checks >= MAX_MISSED_CHECKS {
+                    if let Some(player) = players.remove(&known.mac) {
+                        changes.push(RegistryEvent::Removed(player));
+                    }
+                }
+            }
+        }
+    }
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_announced_at, DeviceRegistry, RegistryEvent};
+    use crate::discover::{DeviceKind, DiscoveredBluOSDevice};
+    use crate::error::Error;
+    use crate::{BluOS, PlayerAddress, Transport, TransportRequest, TransportResponse};
+    use futures::channel::mpsc;
+    use futures::future::BoxFuture;
+    use futures::{SinkExt, StreamExt};
+    use std::time::Duration;
+
+    /// Answers every request with the /SyncStatus of the same player
+    #[derive(Debug)]
+    struct SyncStatusTransport;
+
+    impl Transport for SyncStatusTransport {
+        fn get(&self, _: TransportRequest) -> BoxFuture<'_, Result<TransportResponse, Error>> {
+            Box::pin(async {
+                Ok(TransportResponse {
+                    status: 200,
+                    body: r#"<SyncStatus name="Kitchen" id="192.168.1.100:11000" mac="90:56:82:ab:cd:ef" volume="20"/>"#
+                        .to_string(),
+                })
+            })
+        }
+    }
+
+    fn announcement() -> DiscoveredBluOSDevice {
+        DiscoveredBluOSDevice {
+            name: "Kitchen".to_string(),
+            hostname: "192.168.1.100".to_string(),
+            port: 11000,
+            addresses: vec![
+                "192.168.1.100".parse().unwrap(),
+                "192.168.2.100".parse().unwrap(),
+                "fe80::1".parse().unwrap(),
+            ],
+            interface: None,
+            service_type: "_musc._tcp".to_string(),
+            kind: DeviceKind::Player,
+            txt: Default::default(),
+        }
+    }
+
+    #[test]
+    fn announced_addresses() {
+        let device = announcement();
+        assert!(is_announced_at(
+            &PlayerAddress::new("192.168.1.100", 11000),
+            &device
+        ));
+        assert!(is_announced_at(
+            &PlayerAddress::new("192.168.2.100", 11000),
+            &device
+        ));
+        assert!(is_announced_at(
+            &PlayerAddress::new("fe80::1", 11000),
+            &device
+        ));
+        assert!(!is_announced_at(
+            &PlayerAddress::new("192.168.3.100", 11000),
+            &device
+        ));
+        assert!(!is_announced_at(
+            &PlayerAddress::new("192.168.1.100", 11001),
+            &device
+        ));
+    }
+
+    #[test]
+    fn runs_without_tokio() {
+        let mut registry = DeviceRegistry::with_check_interval(Duration::from_millis(20))
+            .spawn_with(|task| {
+                std::thread::spawn(move || futures::executor::block_on(task));
+            })
+            .player_builder(BluOS::builder().transport(SyncStatusTransport));
+        let (mut tx, rx) = mpsc::channel(10);
+        let mut events = registry.start_with(rx).unwrap();
+
+        futures::executor::block_on(async {
+            tx.send(Ok(announcement())).await.unwrap();
+            let player = match events.next().await.unwrap() {
+                RegistryEvent::Added(player) => player,
+                other => panic!("unexpected {:?}", other),
+            };
+            assert_eq!(player.mac, "90:56:82:AB:CD:EF");
+            assert_eq!(player.bluos().sync_status().await.unwrap().name, "Kitchen");
+
+            // Checks of the player keep going through the transport
+            futures_timer::Delay::new(Duration::from_millis(100)).await;
+            assert!(registry.player_named("kitchen").is_some());
+        });
+    }
+
+    #[test]
+    fn needs_an_executor() {
+        let mut registry = DeviceRegistry::new();
+        assert!(matches!(registry.start(), Err(Error::NoExecutorError)));
+    }
+
+    #[test]
+    fn reports_discovery_errors() {
+        let mut registry = DeviceRegistry::new().spawn_with(|task| {
+            std::thread::spawn(move || futures::executor::block_on(task));
+        });
+        let (mut tx, rx) = mpsc::channel(10);
+        let mut events = registry.start_with(rx).unwrap();
+
+        futures::executor::block_on(async {
+            tx.send(Err(Error::NoBluOSError)).await.unwrap();
+            match events.next().await.unwrap() {
+                RegistryEvent::DiscoveryFailed(e) => {
+                    assert!(matches!(*e, Error::NoBluOSError))
+                }
+                other => panic!("unexpected {:?}", other),
+            }
+        });
+    }
+}
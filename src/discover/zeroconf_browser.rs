@@ -0,0 +1,175 @@
+use super::{
+    blocking_send, interface_of, Backend, DeviceKind, DiscoveredBluOSDevice, SERVICE_TYPES,
+};
+use crate::error::Error;
+use futures::channel::mpsc::{self, Receiver};
+use std::any::Any;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use zeroconf::prelude::*;
+use zeroconf::{MdnsBrowser, ServiceDiscovery, ServiceType};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// The browser is given up on after this many consecutive event loop failures
+const MAX_POLL_FAILURES: u32 = 5;
+
+pub struct Discovery {
+    cancel: Option<std::sync::mpsc::Sender<bool>>,
+}
+
+impl Discovery {
+    pub fn new() -> Discovery {
+        Discovery { cancel: None }
+    }
+    /// Discover uses mDNS to scan the network for BluOS devices
+    /// Returns a channel that streams results as they are found, it works with any executor
+    ///
+    /// Failures of the mDNS browser are sent through the channel as well. The channel is
+    /// closed if the browser can't be started or keeps failing.
+    /// The discovery process is cancelled on drop
+    pub async fn discover(
+        &mut self,
+    ) -> Result<Receiver<Result<DiscoveredBluOSDevice, Error>>, Error> {
+        //Check if we're already doing this
+        if self.cancel.is_some() {
+            return Err(Error::AlreadyDiscovering);
+        }
+
+        let service_types = SERVICE_TYPES
+            .iter()
+            .map(|(service_type, _)| service_type.parse::<ServiceType>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(Error::ServiceTypeError)?;
+
+        let (mut tx, rx) = mpsc::channel(200);
+        let (ctx, crx): (
+            std::sync::mpsc::Sender<bool>,
+            std::sync::mpsc::Receiver<bool>,
+        ) = std::sync::mpsc::channel();
+        self.cancel = Some(ctx);
+
+        std::thread::spawn(move || {
+            // One browser per service type, they have to be kept alive while polling
+            let mut browsers = Vec::with_capacity(service_types.len());
+            let mut event_loops = Vec::with_capacity(service_types.len());
+            for service_type in service_types {
+                let mut browser = MdnsBrowser::new(service_type);
+
+                let callback_tx = tx.clone();
+                browser.set_service_discovered_callback(Box::new(
+                    move |result: zeroconf::Result<ServiceDiscovery>,
+                          _context: Option<Arc<dyn Any>>| {
+                        let res = result.map(discovered).map_err(Error::ResolveError);
+                        let _ = blocking_send(&mut callback_tx.clone(), res);
+                    },
+                ));
+
+                match browser.browse_services() {
+                    Ok(event_loop) => event_loops.push(event_loop),
+                    Err(e) => {
+                        let _ = blocking_send(&mut tx, Err(Error::BrowseError(e)));
+                        return;
+                    }
+                }
+                browsers.push(browser);
+            }
+            let poll_timeout = POLL_INTERVAL / event_loops.len() as u32;
+
+            let mut failures = 0;
+            loop {
+                match event_loops.iter().try_for_each(|l| l.poll(poll_timeout)) {
+                    Ok(()) => failures = 0,
+                    Err(e) => {
+                        // Transient failures are reported and retried, give up if they keep coming
+                        failures += 1;
+                        if blocking_send(&mut tx, Err(Error::EventLoopError(e))).is_err()
+                            || failures >= MAX_POLL_FAILURES
+                        {
+                            return;
+                        }
+                        std::thread::sleep(POLL_INTERVAL);
+                    }
+                }
+
+                match crx.try_recv() {
+                    Ok(_) => return,
+                    Err(e) => match e {
+                        std::sync::mpsc::TryRecvError::Empty => {}
+                        std::sync::mpsc::TryRecvError::Disconnected => return,
+                    },
+                }
+            }
+        });
+
+        Ok(rx)
+    }
+    /// Discover one is a helper function that scans the network and returns the FIRST BluOS player it finds.
+    /// This is useful if you only have one BluOS device.
+    ///
+    /// Errors reported while scanning are skipped, the last one is returned if no device is found
+    /// before the timeout
+    pub async fn discover_one(timeout: Duration) -> Result<DiscoveredBluOSDevice, Error> {
+        super::discover_one::<Self>(timeout).await
+    }
+
+    /// Scans the network until the timeout and returns every BluOS device found, once per name and kind.
+    /// Besides players this includes hubs, servers and secondary players
+    pub async fn discover_all(timeout: Duration) -> Result<Vec<DiscoveredBluOSDevice>, Error> {
+        super::discover_all::<Self>(timeout).await
+    }
+
+    /// Scans the network for the BluOS player with the given name, ignoring case
+    pub async fn discover_named(
+        name: &str,
+        timeout: Duration,
+    ) -> Result<DiscoveredBluOSDevice, Error> {
+        super::discover_named::<Self>(name, timeout).await
+    }
+}
+
+fn discovered(res: ServiceDiscovery) -> DiscoveredBluOSDevice {
+    let service_type = format!(
+        "_{}._{}",
+        res.service_type().name(),
+        res.service_type().protocol()
+    );
+    // Avahi and Bonjour resolve every address of a service on its own, so each result only
+    // carries one. Results are sent as they come rather than held back to merge them
+    let addresses: Vec<IpAddr> = res.address().parse().into_iter().collect();
+
+    DiscoveredBluOSDevice {
+        name: res.name().clone(),
+        hostname: res.address().clone(),
+        port: *res.port(),
+        interface: interface_of(&addresses),
+        addresses,
+        kind: DeviceKind::from_service_type(&service_type),
+        service_type,
+        txt: res
+            .txt()
+            .as_ref()
+            .map(|txt| txt.iter().collect())
+            .unwrap_or_default(),
+    }
+}
+
+impl Default for Discovery {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Backend for Discovery {
+    async fn discover(&mut self) -> Result<Receiver<Result<DiscoveredBluOSDevice, Error>>, Error> {
+        Discovery::discover(self).await
+    }
+}
+
+impl Drop for Discovery {
+    fn drop(&mut self) {
+        if let Some(c) = &self.cancel {
+            let _ = c.send(true);
+        }
+    }
+}
@@ -8,18 +8,83 @@ pub enum Error {
     #[error("Could not find BluOS controller")]
     NoBluOSError,
 
+    #[cfg(feature = "reqwest")]
     #[error(transparent)]
     RequestError(#[from] reqwest::Error),
 
+    #[error("Transport failed: {0}")]
+    TransportError(Box<dyn std::error::Error + Send + Sync>),
+
+    #[error(transparent)]
+    UrlError(#[from] url::ParseError),
+
+    #[error("Invalid player address {address}: {reason}")]
+    AddressError {
+        address: String,
+        reason: &'static str,
+    },
+
+    #[error("Invalid header value {0:?}")]
+    HeaderError(String),
+
     #[error(transparent)]
     XMLError(#[from] serde_xml_rs::Error),
 
+    #[error("{endpoint} failed with HTTP status {status}")]
+    HttpStatusError {
+        endpoint: String,
+        status: u16,
+        /// Raw body of the response, for debugging
+        body: String,
+    },
+
+    #[error("{endpoint} was rejected by the device: {message}")]
+    DeviceError {
+        endpoint: String,
+        status: u16,
+        message: String,
+        /// Raw body of the response, for debugging
+        body: String,
+    },
+
     #[error(transparent)]
     CancelError(#[from] std::sync::mpsc::SendError<bool>),
 
     #[error("Already discovering using zeroconf")]
     AlreadyDiscovering,
 
-    #[error("IDK BRO")]
-    Unknown,
+    #[error("No executor to run on, use spawn_with to provide one")]
+    NoExecutorError,
+
+    #[error("No transport to send requests with, enable the reqwest feature or use BluOSBuilder::transport")]
+    NoTransportError,
+
+    #[cfg(feature = "discover-mdns-sd")]
+    #[error(transparent)]
+    MdnsError(#[from] mdns_sd::Error),
+
+    #[cfg(feature = "discover")]
+    #[error("Invalid mDNS service type: {0}")]
+    ServiceTypeError(zeroconf::error::Error),
+
+    #[cfg(feature = "discover")]
+    #[error("Could not start browsing for mDNS services: {0}")]
+    BrowseError(zeroconf::error::Error),
+
+    #[cfg(feature = "discover")]
+    #[error("mDNS event loop failed: {0}")]
+    EventLoopError(zeroconf::error::Error),
+
+    #[cfg(feature = "discover")]
+    #[error("Could not resolve discovered service: {0}")]
+    ResolveError(zeroconf::error::Error),
+
+    #[error("Invalid LSDP packet: {0}")]
+    LsdpError(&'static str),
+
+    #[error("Cassette failed: {0}")]
+    CassetteError(String),
+
+    #[error("Browse item has no play URL")]
+    NotPlayableError,
 }
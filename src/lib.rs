@@ -1,8 +1,29 @@
+#[cfg(feature = "blocking")]
+pub mod blocking;
+mod cassette;
 mod device;
 mod discover;
 mod error;
+mod sim;
 
-pub use device::{BluOS, Playlist, PlaylistEntry, RepeatSetting, State, Status};
-#[cfg(feature = "discover")]
-pub use discover::{DiscoveredBluOSDevice, Discovery};
+#[cfg(feature = "cassette")]
+pub use cassette::CassetteTransport;
+#[cfg(feature = "reqwest")]
+pub use device::ReqwestTransport;
+pub use device::{
+    Battery, BluOS, BluOSBuilder, Browse, BrowseItem, DeviceInfo, GroupTopology, Pagination,
+    PlayerAddress, PlayerGroup, Playlist, PlaylistEntry, Preset, Presets, RawCommand,
+    RepeatSetting, RetryPolicy, State, Status, StatusEvent, SyncMaster, SyncSlave, SyncStatus,
+    Transport, TransportRequest, TransportResponse, VolumeResponse,
+};
+#[cfg(any(feature = "discover", feature = "discover-mdns-sd"))]
+pub use discover::Discovery;
+#[cfg(feature = "lsdp")]
+pub use discover::LsdpDiscovery;
+#[cfg(any(feature = "discover", feature = "discover-mdns-sd", feature = "lsdp"))]
+pub use discover::{
+    DeviceKind, DeviceRegistry, DiscoveredBluOSDevice, RegisteredPlayer, RegistryEvent,
+};
 pub use error::Error;
+#[cfg(feature = "sim")]
+pub use sim::{SimBrowseItem, SimModel, SimTrack, SimulatedPlayer};
@@ -0,0 +1,957 @@
+#![cfg(feature = "sim")]
+use crate::device::Preset;
+use crate::error::Error;
+use crate::BluOS;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+
+/// Longest time a long-polling /Status request is held
+const MAX_LONG_POLL: Duration = Duration::from_secs(100);
+/// How long the simulated player pretends to index after /Reindex
+const INDEXING_TIME: Duration = Duration::from_millis(200);
+/// Requests with a bigger header than this are rejected
+const MAX_REQUEST_SIZE: usize = 16 * 1024;
+
+/// A simulated BluOS player serving the BluOS HTTP XML API
+///
+/// The player is backed by an in-memory SimModel. Use it to exercise BluOS
+/// without any hardware, the server is shut down when the player is dropped.
+pub struct SimulatedPlayer {
+    addr: SocketAddr,
+    shared: Arc<Shared>,
+    server: JoinHandle<()>,
+}
+
+impl SimulatedPlayer {
+    /// Start a simulated player with the default model on a random port on localhost
+    pub async fn start() -> Result<SimulatedPlayer, Error> {
+        SimulatedPlayer::bind(
+            SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
+            SimModel::default(),
+        )
+        .await
+    }
+
+    /// Start a simulated player for a model on the given address
+    pub async fn bind(addr: SocketAddr, model: SimModel) -> Result<SimulatedPlayer, Error> {
+        let listener = TcpListener::bind(addr).await?;
+        let addr = listener.local_addr()?;
+        let (etag, _) = watch::channel(1);
+        let shared = Arc::new(Shared {
+            addr,
+            model: Mutex::new(model),
+            etag,
+        });
+
+        let server = tokio::spawn(serve(listener, shared.clone()));
+
+        Ok(SimulatedPlayer {
+            addr,
+            shared,
+            server,
+        })
+    }
+
+    /// The address the simulated player listens on
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Create a BluOS client talking to this simulated player
+    pub fn client(&self) -> Result<BluOS, Error> {
+        BluOS::with_socket_addr(self.addr)
+    }
+
+    /// A copy of the current state of the player
+    pub fn model(&self) -> SimModel {
+        self.shared.model.lock().unwrap().clone()
+    }
+
+    /// Change the state of the player, this wakes up long-polling /Status requests
+    ///
+    /// Remember to bump prid when changing the presets and pid when changing the queue
+    pub fn update<F: FnOnce(&mut SimModel)>(&self, f: F) {
+        f(&mut self.shared.model.lock().unwrap());
+        self.shared.changed();
+    }
+}
+
+impl Drop for SimulatedPlayer {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
+}
+
+/// The in-memory state of a simulated player
+#[derive(Debug, Clone)]
+pub struct SimModel {
+    pub name: String,
+    pub model: String,
+    pub model_name: String,
+    pub brand: String,
+    pub mac: String,
+
+    /// play, pause, stop or stream
+    pub state: String,
+    /// Seconds played of the current track
+    pub secs: i64,
+    /// URL of the stream playing, if not playing from the queue
+    pub stream_url: Option<String>,
+    pub input_type: Option<String>,
+
+    /// Volume in percentage, 0 to 100
+    pub volume: u8,
+    pub muted: bool,
+    /// Volume before muting
+    pub mute_volume: u8,
+
+    pub shuffle: bool,
+    /// 0 repeat queue, 1 repeat track, 2 repeat off
+    pub repeat: u8,
+
+    pub queue: Vec<SimTrack>,
+    pub queue_name: Option<String>,
+    pub queue_modified: bool,
+    /// Position of the current track in the queue
+    pub song: usize,
+    /// Play queue id, bumped every time the queue changes
+    pub pid: i64,
+
+    pub presets: Vec<Preset>,
+    /// Preset id, bumped every time the presets change
+    pub prid: u8,
+    pub current_preset: Option<usize>,
+
+    /// Browse menus by browse key, the root menu has the empty key
+    pub browse: HashMap<String, Vec<SimBrowseItem>>,
+
+    pub group_name: Option<String>,
+    pub slaves: Vec<(String, u16)>,
+    pub master: Option<(String, u16)>,
+
+    pub indexing: bool,
+
+    /// The next requests are answered with 503 Service Unavailable and an error document,
+    /// like a busy player
+    pub busy_responses: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimTrack {
+    pub title: String,
+    pub artist: String,
+    pub album: String,
+    /// Length in seconds
+    pub length: i64,
+}
+
+impl SimTrack {
+    pub fn new(title: &str, artist: &str, album: &str, length: i64) -> SimTrack {
+        SimTrack {
+            title: title.to_string(),
+            artist: artist.to_string(),
+            album: album.to_string(),
+            length,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimBrowseItem {
+    pub text: String,
+    /// link, audio, etc.
+    pub item_type: String,
+    pub browse_key: Option<String>,
+    pub play_url: Option<String>,
+    pub image: Option<String>,
+}
+
+impl SimBrowseItem {
+    /// An item that opens another browse menu
+    pub fn link(text: &str, browse_key: &str) -> SimBrowseItem {
+        SimBrowseItem {
+            text: text.to_string(),
+            item_type: "link".to_string(),
+            browse_key: Some(browse_key.to_string()),
+            play_url: None,
+            image: None,
+        }
+    }
+
+    /// An item that can be played, the play URL is relative to the player
+    pub fn audio(text: &str, play_url: &str) -> SimBrowseItem {
+        SimBrowseItem {
+            text: text.to_string(),
+            item_type: "audio".to_string(),
+            browse_key: None,
+            play_url: Some(play_url.to_string()),
+            image: None,
+        }
+    }
+}
+
+impl Default for SimModel {
+    fn default() -> Self {
+        let mut browse = HashMap::new();
+        browse.insert(
+            String::new(),
+            vec![
+                SimBrowseItem::link("Library", "LocalMusic:"),
+                SimBrowseItem::link("Search", "Search:artist=Miles Davis&type=album"),
+            ],
+        );
+        browse.insert(
+            "LocalMusic:".to_string(),
+            vec![
+                SimBrowseItem::audio("So What", "/Play?url=LocalMusic%3Atrack%2F1"),
+                SimBrowseItem::audio("Blue in Green", "/Play?url=LocalMusic%3Atrack%2F3"),
+            ],
+        );
+        browse.insert(
+            "Search:artist=Miles Davis&type=album".to_string(),
+            vec![SimBrowseItem::link("Kind of Blue", "LocalMusic:")],
+        );
+
+        SimModel {
+            name: "Simulated Player".to_string(),
+            model: "SIM1".to_string(),
+            model_name: "Simulated Player".to_string(),
+            brand: "Bluesound".to_string(),
+            mac: "02:00:00:00:00:01".to_string(),
+
+            state: "stop".to_string(),
+            secs: 0,
+            stream_url: None,
+            input_type: None,
+
+            volume: 25,
+            muted: false,
+            mute_volume: 0,
+
+            shuffle: false,
+            repeat: 2,
+
+            queue: vec![
+                SimTrack::new("So What", "Miles Davis", "Kind of Blue", 562),
+                SimTrack::new("Freddie Freeloader", "Miles Davis", "Kind of Blue", 589),
+                SimTrack::new("Blue in Green", "Miles Davis", "Kind of Blue", 337),
+            ],
+            queue_name: Some("Kind of Blue".to_string()),
+            queue_modified: false,
+            song: 0,
+            pid: 1,
+
+            presets: vec![
+                Preset {
+                    id: 1,
+                    name: "Radio Paradise".to_string(),
+                    url: "RadioParadise:/0:4".to_string(),
+                    image: None,
+                },
+                Preset {
+                    id: 2,
+                    name: "Jazz24".to_string(),
+                    url: "TuneIn:s34682".to_string(),
+                    image: None,
+                },
+            ],
+            prid: 1,
+            current_preset: None,
+
+            browse,
+
+            group_name: None,
+            slaves: Vec::new(),
+            master: None,
+
+            indexing: false,
+
+            busy_responses: 0,
+        }
+    }
+}
+
+impl SimModel {
+    fn volume_db(&self) -> f64 {
+        level_to_db(self.volume)
+    }
+
+    fn set_volume(&mut self, level: u8) {
+        self.volume = level.min(100);
+        self.muted = false;
+    }
+
+    fn current_track(&self) -> Option<&SimTrack> {
+        if self.stream_url.is_some() {
+            return None;
+        }
+        self.queue.get(self.song)
+    }
+
+    fn queue_changed(&mut self) {
+        self.pid += 1;
+        self.queue_modified = true;
+        if self.song >= self.queue.len() {
+            self.song = 0;
+        }
+        if self.queue.is_empty() && self.stream_url.is_none() {
+            self.state = "stop".to_string();
+        }
+    }
+}
+
+/// Map the volume level to decibel, 0 is -80 dB and 100 is 0 dB
+fn level_to_db(level: u8) -> f64 {
+    (level as f64 - 100.0) * 0.8
+}
+
+fn db_to_level(db: f64) -> u8 {
+    (db / 0.8 + 100.0).round().clamp(0.0, 100.0) as u8
+}
+
+struct Shared {
+    addr: SocketAddr,
+    model: Mutex<SimModel>,
+    etag: watch::Sender<u64>,
+}
+
+impl Shared {
+    fn changed(&self) {
+        self.etag.send_modify(|e| *e += 1);
+    }
+
+    fn etag(&self) -> u64 {
+        *self.etag.borrow()
+    }
+}
+
+struct Request {
+    endpoint: String,
+    params: Vec<(String, String)>,
+}
+
+impl Request {
+    fn param(&self, key: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+struct Response {
+    status: u16,
+    body: String,
+}
+
+impl Response {
+    fn ok(body: String) -> Response {
+        Response { status: 200, body }
+    }
+
+    fn error(status: u16, message: &str) -> Response {
+        Response {
+            status,
+            body: format!("<error>{}</error>", escape(message)),
+        }
+    }
+}
+
+async fn serve(listener: TcpListener, shared: Arc<Shared>) {
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(_) => continue,
+        };
+        let shared = shared.clone();
+        tokio::spawn(async move {
+            let _ = handle_connection(stream, shared).await;
+        });
+    }
+}
+
+async fn handle_connection(mut stream: TcpStream, shared: Arc<Shared>) -> std::io::Result<()> {
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 1024];
+    while !buf.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            return Ok(());
+        }
+        buf.extend_from_slice(&chunk[..n]);
+        if buf.len() > MAX_REQUEST_SIZE {
+            return write_response(&mut stream, Response::error(400, "Request too large")).await;
+        }
+    }
+
+    let head = String::from_utf8_lossy(&buf);
+    let target =
+        head.lines()
+            .next()
+            .and_then(|line| match line.split(' ').collect::<Vec<_>>()[..] {
+                ["GET", target, _] => Some(target.to_string()),
+                _ => None,
+            });
+
+    let response = match target {
+        Some(target) => handle_request(parse_target(&target), &shared).await,
+        None => Response::error(400, "Bad request"),
+    };
+
+    write_response(&mut stream, response).await
+}
+
+async fn write_response(stream: &mut TcpStream, response: Response) -> std::io::Result<()> {
+    let reason = match response.status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        503 => "Service Unavailable",
+        _ => "Error",
+    };
+    let head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: text/xml; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        reason,
+        response.body.len()
+    );
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(response.body.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+fn parse_target(target: &str) -> Request {
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    Request {
+        endpoint: path.trim_start_matches('/').to_string(),
+        // Percent-decoding only, like a player a + stays a +
+        params: query
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+                (decode(key), decode(value))
+            })
+            .collect(),
+    }
+}
+
+fn decode(s: &str) -> String {
+    percent_encoding::percent_decode_str(s)
+        .decode_utf8_lossy()
+        .into_owned()
+}
+
+async fn handle_request(req: Request, shared: &Arc<Shared>) -> Response {
+    {
+        let mut m = shared.model.lock().unwrap();
+        if m.busy_responses > 0 {
+            m.busy_responses -= 1;
+            return Response {
+                status: 503,
+                body: "<error><message>Service unavailable</message></error>".to_string(),
+            };
+        }
+    }
+
+    if req.endpoint == "Status" {
+        return status(&req, shared).await;
+    }
+
+    let mut changed = true;
+    let response = {
+        let mut m = shared.model.lock().unwrap();
+        match req.endpoint.as_str() {
+            "SyncStatus" => {
+                changed = false;
+                Response::ok(sync_status_xml(&m, shared.addr))
+            }
+            "Volume" => volume(&req, &mut m, &mut changed),
+            "Play" => play(&req, &mut m),
+            "Pause" => {
+                m.state = match (req.param("toggle"), m.state.as_str()) {
+                    (Some("1"), "pause") => "play".to_string(),
+                    _ => "pause".to_string(),
+                };
+                Response::ok(state_xml(&m.state))
+            }
+            "Stop" => {
+                m.state = "stop".to_string();
+                Response::ok(state_xml(&m.state))
+            }
+            "Skip" => {
+                if !m.queue.is_empty() {
+                    m.song = (m.song + 1) % m.queue.len();
+                    m.secs = 0;
+                }
+                Response::ok(id_xml(&m))
+            }
+            "Back" => {
+                if !m.queue.is_empty() {
+                    if m.secs <= 4 {
+                        m.song = m.song.checked_sub(1).unwrap_or(m.queue.len() - 1);
+                    }
+                    m.secs = 0;
+                }
+                Response::ok(id_xml(&m))
+            }
+            "Shuffle" => {
+                m.shuffle = req.param("state") == Some("1");
+                Response::ok(playlist_xml(&m, None))
+            }
+            "Repeat" => match req.param("state").and_then(|s| s.parse().ok()) {
+                Some(state @ 0..=2) => {
+                    m.repeat = state;
+                    Response::ok(playlist_xml(&m, None))
+                }
+                _ => Response::error(400, "Invalid repeat state"),
+            },
+            "Playlist" => {
+                changed = false;
+                let start = req.param("start").and_then(|s| s.parse().ok());
+                let end = req.param("end").and_then(|s| s.parse().ok());
+                let last = m.queue.len().saturating_sub(1);
+                let range = (start.unwrap_or(0), end.unwrap_or(last));
+                Response::ok(playlist_xml(&m, Some(range)))
+            }
+            "Delete" => match req.param("id").and_then(|s| s.parse::<usize>().ok()) {
+                Some(id) if id < m.queue.len() => {
+                    m.queue.remove(id);
+                    if id < m.song {
+                        m.song -= 1;
+                    }
+                    m.queue_changed();
+                    Response::ok(playlist_xml(&m, None))
+                }
+                _ => Response::error(400, "Invalid song id"),
+            },
+            "Clear" => {
+                m.queue.clear();
+                m.queue_name = None;
+                m.queue_changed();
+                Response::ok(playlist_xml(&m, None))
+            }
+            "Browse" => {
+                changed = false;
+                let key = req.param("key").unwrap_or_default();
+                match m.browse.get(key) {
+                    Some(items) => Response::ok(browse_xml(items)),
+                    None => Response::error(200, "Invalid browse key"),
+                }
+            }
+            "Reindex" => {
+                m.indexing = true;
+                let shared = shared.clone();
+                tokio::spawn(async move {
+                    tokio::time::sleep(INDEXING_TIME).await;
+                    shared.model.lock().unwrap().indexing = false;
+                    shared.changed();
+                });
+                Response::ok("<indexing>1</indexing>".to_string())
+            }
+            "Presets" => {
+                changed = false;
+                Response::ok(presets_xml(&m))
+            }
+            "Preset" => preset(&req, &mut m),
+            "AddSlave" => match (req.param("slave"), req.param("port")) {
+                (Some(slave), Some(port)) => {
+                    let added = add_slaves(&mut m, &[slave], &[port], req.param("group"));
+                    Response::ok(add_slave_xml(&added))
+                }
+                _ => Response::error(400, "Missing slave or port"),
+            },
+            "AddSlaves" => match (req.param("slaves"), req.param("ports")) {
+                (Some(slaves), Some(ports)) => {
+                    let slaves: Vec<&str> = slaves.split(',').collect();
+                    let ports: Vec<&str> = ports.split(',').collect();
+                    if slaves.len() != ports.len() {
+                        Response::error(400, "Number of slaves and ports differ")
+                    } else {
+                        let added = add_slaves(&mut m, &slaves, &ports, req.param("group"));
+                        Response::ok(add_slave_xml(&added))
+                    }
+                }
+                _ => Response::error(400, "Missing slaves or ports"),
+            },
+            "RemoveSlave" => {
+                let slave = req.param("slave").unwrap_or_default();
+                let port: Option<u16> = req.param("port").and_then(|p| p.parse().ok());
+                m.slaves.retain(|(s, p)| !(s == slave && Some(*p) == port));
+                if m.slaves.is_empty() {
+                    m.group_name = None;
+                }
+                Response::ok(sync_status_xml(&m, shared.addr))
+            }
+            _ => {
+                changed = false;
+                Response::error(404, "Unknown command")
+            }
+        }
+    };
+
+    if changed && response.status == 200 {
+        shared.changed();
+    }
+
+    response
+}
+
+async fn status(req: &Request, shared: &Arc<Shared>) -> Response {
+    // Long-polling, hold the request until the etag changes or the timeout passes
+    if let (Some(etag), Some(timeout)) = (req.param("etag"), req.param("timeout")) {
+        let timeout = timeout
+            .parse()
+            .map(Duration::from_secs)
+            .unwrap_or(MAX_LONG_POLL)
+            .min(MAX_LONG_POLL);
+        let mut rx = shared.etag.subscribe();
+        if etag == shared.etag().to_string() {
+            let _ = tokio::time::timeout(timeout, rx.changed()).await;
+        }
+    }
+
+    let m = shared.model.lock().unwrap();
+    Response::ok(status_xml(&m, shared.etag()))
+}
+
+fn volume(req: &Request, m: &mut SimModel, changed: &mut bool) -> Response {
+    if let Some(level) = req.param("level") {
+        match level.parse::<u8>() {
+            Ok(level) => m.set_volume(level),
+            Err(_) => return Response::error(400, "Invalid level"),
+        }
+    } else if let Some(db) = req.param("abs_db") {
+        match db.parse::<f64>() {
+            Ok(db) => m.set_volume(db_to_level(db)),
+            Err(_) => return Response::error(400, "Invalid abs_db"),
+        }
+    } else if let Some(db) = req.param("db") {
+        match db.parse::<f64>() {
+            Ok(db) => m.set_volume(db_to_level(m.volume_db() + db)),
+            Err(_) => return Response::error(400, "Invalid db"),
+        }
+    } else if let Some(mute) = req.param("mute") {
+        match mute {
+            "1" if !m.muted => {
+                m.mute_volume = m.volume;
+                m.volume = 0;
+                m.muted = true;
+            }
+            "0" if m.muted => {
+                m.volume = m.mute_volume;
+                m.muted = false;
+            }
+            "0" | "1" => {}
+            _ => return Response::error(400, "Invalid mute"),
+        }
+    } else {
+        *changed = false;
+    }
+
+    Response::ok(volume_xml(m))
+}
+
+fn play(req: &Request, m: &mut SimModel) -> Response {
+    if let Some(url) = req.param("url") {
+        m.stream_url = Some(url.to_string());
+        m.current_preset = None;
+        m.secs = 0;
+        m.state = "stream".to_string();
+        return Response::ok(state_xml(&m.state));
+    }
+
+    if let Some(input_type) = req.param("inputType") {
+        m.input_type = Some(input_type.to_string());
+        m.stream_url = None;
+    }
+    if let Some(seek) = req.param("seek") {
+        match seek.parse() {
+            Ok(seek) => m.secs = seek,
+            Err(_) => return Response::error(400, "Invalid seek"),
+        }
+    }
+
+    if m.stream_url.is_some() {
+        m.state = "stream".to_string();
+    } else if !m.queue.is_empty() || m.input_type.is_some() {
+        m.state = "play".to_string();
+    }
+    Response::ok(state_xml(&m.state))
+}
+
+fn preset(req: &Request, m: &mut SimModel) -> Response {
+    if m.presets.is_empty() {
+        return Response::error(200, "No presets");
+    }
+    let last = m.presets.len() - 1;
+    let index = match req.param("id") {
+        Some("+1") => m.current_preset.map(|i| if i == last { 0 } else { i + 1 }),
+        Some("-1") => m.current_preset.map(|i| i.checked_sub(1).unwrap_or(last)),
+        Some(id) => {
+            let id: Option<i64> = id.parse().ok();
+            match m.presets.iter().position(|p| Some(p.id) == id) {
+                Some(index) => Some(index),
+                None => return Response::error(200, "Invalid preset id"),
+            }
+        }
+        None => return Response::error(400, "Missing preset id"),
+    };
+
+    let index = index.unwrap_or(0);
+    m.current_preset = Some(index);
+    m.stream_url = Some(m.presets[index].url.clone());
+    m.secs = 0;
+    m.state = "stream".to_string();
+    Response::ok(state_xml(&m.state))
+}
+
+fn add_slaves(
+    m: &mut SimModel,
+    slaves: &[&str],
+    ports: &[&str],
+    group: Option<&str>,
+) -> Vec<(String, u16)> {
+    let mut added = Vec::new();
+    for (slave, port) in slaves.iter().zip(ports) {
+        let slave = (slave.to_string(), port.parse().unwrap_or(11000));
+        if !m.slaves.contains(&slave) {
+            m.slaves.push(slave.clone());
+        }
+        added.push(slave);
+    }
+    if let Some(group) = group {
+        m.group_name = Some(group.to_string());
+    } else if m.group_name.is_none() {
+        m.group_name = Some(format!("{} +{}", m.name, m.slaves.len()));
+    }
+    added
+}
+
+///////////////////
+// XML documents
+///////////////////
+
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn element(xml: &mut String, name: &str, value: impl ToString) {
+    let _ = write!(xml, "<{0}>{1}</{0}>", name, escape(&value.to_string()));
+}
+
+fn attribute(xml: &mut String, name: &str, value: impl ToString) {
+    let _ = write!(xml, " {}=\"{}\"", name, escape(&value.to_string()));
+}
+
+fn status_xml(m: &SimModel, etag: u64) -> String {
+    let mut xml = format!("<status etag=\"{}\">", etag);
+
+    if let Some(track) = m.current_track() {
+        element(&mut xml, "album", &track.album);
+        element(&mut xml, "artist", &track.artist);
+        element(&mut xml, "name", &track.title);
+        element(&mut xml, "title1", &track.title);
+        element(&mut xml, "title2", &track.artist);
+        element(&mut xml, "title3", &track.album);
+        element(&mut xml, "totlen", track.length);
+    } else if let Some(url) = &m.stream_url {
+        let title = match m.current_preset.and_then(|i| m.presets.get(i)) {
+            Some(preset) => preset.name.clone(),
+            None => url.clone(),
+        };
+        element(&mut xml, "title1", title);
+        element(&mut xml, "streamUrl", url);
+    }
+
+    element(&mut xml, "db", m.volume_db());
+    element(&mut xml, "volume", m.volume);
+    element(&mut xml, "mute", m.muted as u8);
+    if m.muted {
+        element(&mut xml, "muteVolume", m.mute_volume);
+        element(&mut xml, "muteDb", level_to_db(m.mute_volume).round());
+    }
+    if let Some(group) = &m.group_name {
+        element(&mut xml, "groupName", group);
+        element(&mut xml, "groupVolume", m.volume);
+    }
+    element(&mut xml, "indexing", m.indexing as u8);
+    element(&mut xml, "mid", 1);
+    element(&mut xml, "mode", 1);
+    element(&mut xml, "pid", m.pid);
+    element(&mut xml, "prid", m.prid);
+    element(&mut xml, "repeat", m.repeat);
+    element(&mut xml, "secs", m.secs);
+    element(&mut xml, "shuffle", m.shuffle as u8);
+    element(&mut xml, "sid", 1);
+    element(&mut xml, "song", m.song);
+    element(&mut xml, "state", &m.state);
+    element(&mut xml, "syncStat", etag);
+
+    xml.push_str("</status>");
+    xml
+}
+
+fn sync_status_xml(m: &SimModel, addr: SocketAddr) -> String {
+    let mut xml = "<SyncStatus".to_string();
+    attribute(&mut xml, "name", &m.name);
+    attribute(&mut xml, "id", addr);
+    attribute(&mut xml, "mac", &m.mac);
+    attribute(&mut xml, "model", &m.model);
+    attribute(&mut xml, "modelName", &m.model_name);
+    attribute(&mut xml, "brand", &m.brand);
+    attribute(&mut xml, "schemaVersion", 32);
+    attribute(
+        &mut xml,
+        "icon",
+        format!("/images/players/{}_nt.png", m.model),
+    );
+    attribute(&mut xml, "initialized", true);
+    attribute(&mut xml, "volume", m.volume);
+    attribute(&mut xml, "db", m.volume_db());
+    attribute(&mut xml, "mute", m.muted as u8);
+    if let Some(group) = &m.group_name {
+        attribute(&mut xml, "group", group);
+    }
+    xml.push('>');
+
+    if let Some((master, port)) = &m.master {
+        let _ = write!(xml, "<master port=\"{}\">{}</master>", port, escape(master));
+    }
+    for (slave, port) in &m.slaves {
+        let _ = write!(xml, "<slave id=\"{}\" port=\"{}\"/>", escape(slave), port);
+    }
+
+    xml.push_str("</SyncStatus>");
+    xml
+}
+
+fn volume_xml(m: &SimModel) -> String {
+    let mut xml = "<volume".to_string();
+    attribute(&mut xml, "db", m.volume_db());
+    attribute(&mut xml, "mute", m.muted as u8);
+    if m.muted {
+        attribute(&mut xml, "muteVolume", m.mute_volume);
+        attribute(&mut xml, "muteDb", level_to_db(m.mute_volume));
+    }
+    attribute(&mut xml, "offsetDb", 0);
+    let _ = write!(xml, ">{}</volume>", m.volume);
+    xml
+}
+
+fn state_xml(state: &str) -> String {
+    format!("<state>{}</state>", escape(state))
+}
+
+fn id_xml(m: &SimModel) -> String {
+    if m.queue.is_empty() {
+        "<id/>".to_string()
+    } else {
+        format!("<id>{}</id>", m.song)
+    }
+}
+
+/// The playlist document, songs in the inclusive range are listed
+fn playlist_xml(m: &SimModel, range: Option<(usize, usize)>) -> String {
+    let mut xml = "<playlist".to_string();
+    if let Some(name) = &m.queue_name {
+        attribute(&mut xml, "name", name);
+    }
+    attribute(&mut xml, "modified", m.queue_modified as u8);
+    attribute(&mut xml, "length", m.queue.len());
+    attribute(&mut xml, "id", m.pid);
+
+    let songs: Vec<(usize, &SimTrack)> = match range {
+        Some((start, end)) => m
+            .queue
+            .iter()
+            .enumerate()
+            .skip(start)
+            .take((end + 1).saturating_sub(start))
+            .collect(),
+        None => Vec::new(),
+    };
+    if songs.is_empty() {
+        xml.push_str("/>");
+        return xml;
+    }
+
+    xml.push('>');
+    for (id, track) in songs {
+        xml.push_str("<song");
+        attribute(&mut xml, "id", id);
+        attribute(&mut xml, "service", "LocalMusic");
+        attribute(&mut xml, "songid", id + 1);
+        attribute(&mut xml, "title", &track.title);
+        attribute(&mut xml, "art", &track.artist);
+        attribute(&mut xml, "alb", &track.album);
+        attribute(&mut xml, "quality", "cd");
+        xml.push_str("/>");
+    }
+    xml.push_str("</playlist>");
+    xml
+}
+
+fn browse_xml(items: &[SimBrowseItem]) -> String {
+    let mut xml = "<browse sid=\"1\" type=\"menu\">".to_string();
+    for item in items {
+        xml.push_str("<item");
+        attribute(&mut xml, "text", &item.text);
+        attribute(&mut xml, "type", &item.item_type);
+        if let Some(key) = &item.browse_key {
+            attribute(&mut xml, "browseKey", key);
+        }
+        if let Some(url) = &item.play_url {
+            attribute(&mut xml, "playURL", url);
+        }
+        if let Some(image) = &item.image {
+            attribute(&mut xml, "image", image);
+        }
+        xml.push_str("/>");
+    }
+    xml.push_str("</browse>");
+    xml
+}
+
+fn presets_xml(m: &SimModel) -> String {
+    let mut xml = format!("<presets prid=\"{}\">", m.prid);
+    for preset in &m.presets {
+        xml.push_str("<preset");
+        attribute(&mut xml, "id", preset.id);
+        attribute(&mut xml, "name", &preset.name);
+        attribute(&mut xml, "url", &preset.url);
+        if let Some(image) = &preset.image {
+            attribute(&mut xml, "image", image);
+        }
+        xml.push_str("/>");
+    }
+    xml.push_str("</presets>");
+    xml
+}
+
+fn add_slave_xml(slaves: &[(String, u16)]) -> String {
+    let mut xml = "<addSlave>".to_string();
+    for (slave, port) in slaves {
+        let _ = write!(xml, "<slave id=\"{}\" port=\"{}\"/>", escape(slave), port);
+    }
+    xml.push_str("</addSlave>");
+    xml
+}
@@ -0,0 +1,40 @@
+use bluos_api_rs::{blocking, RawCommand, SimulatedPlayer, State};
+use tokio::runtime::Runtime;
+
+/// The simulated player needs a runtime of its own while the blocking client is used
+fn start_sim() -> (Runtime, SimulatedPlayer) {
+    let rt = Runtime::new().unwrap();
+    let sim = rt.block_on(SimulatedPlayer::start()).unwrap();
+    (rt, sim)
+}
+
+#[test]
+fn blocking_client() {
+    let (_rt, sim) = start_sim();
+    let bluos = blocking::BluOS::with_socket_addr(sim.addr()).unwrap();
+
+    let status = bluos.status().unwrap();
+    assert_eq!(status.state, "stop");
+    assert_eq!(bluos.sync_status().unwrap().name, "Simulated Player");
+
+    assert_eq!(bluos.play().unwrap(), State::Play);
+    assert_eq!(bluos.set_volume(40, None).unwrap().volume, 40);
+    assert_eq!(sim.model().volume, 40);
+
+    let xml = bluos.send_raw(RawCommand::new("Status")).unwrap();
+    assert!(xml.contains("<state>play</state>"), "{}", xml);
+}
+
+#[test]
+fn blocking_status_stream() {
+    let (_rt, sim) = start_sim();
+    let bluos = blocking::BluOS::with_socket_addr(sim.addr()).unwrap();
+
+    let mut statuses = bluos.status_stream();
+    let first = statuses.next().unwrap().unwrap();
+    assert_eq!(first.state, "stop");
+
+    sim.update(|m| m.volume = 60);
+    let next = statuses.next().unwrap().unwrap();
+    assert_eq!(next.volume, 60);
+}
@@ -0,0 +1,585 @@
+use bluos_api_rs::{
+    BluOS, Error, GroupTopology, Pagination, PlayerAddress, RawCommand, RepeatSetting,
+    ReqwestTransport, RetryPolicy, SimBrowseItem, SimulatedPlayer, State, StatusEvent, SyncStatus,
+    Transport, TransportRequest, TransportResponse,
+};
+use futures::future::BoxFuture;
+use futures::StreamExt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+#[tokio::test]
+async fn status() {
+    let sim = SimulatedPlayer::start().await.unwrap();
+    let bluos = sim.client().unwrap();
+
+    let status = bluos.status().await.unwrap();
+    assert_eq!(status.state, "stop");
+    assert_eq!(status.volume, 25);
+    assert_eq!(status.name.as_deref(), Some("So What"));
+    assert_eq!(status.artist.as_deref(), Some("Miles Davis"));
+    assert_eq!(status.song_queue_position, 0);
+}
+
+#[tokio::test]
+async fn sync_status_and_device_info() {
+    let sim = SimulatedPlayer::start().await.unwrap();
+    let bluos = sim.client().unwrap();
+
+    let sync_status = bluos.sync_status().await.unwrap();
+    assert_eq!(sync_status.name, "Simulated Player");
+    assert_eq!(sync_status.id, sim.addr().to_string());
+
+    let info = bluos.device_info().await.unwrap();
+    assert_eq!(info.mac.as_deref(), Some("02:00:00:00:00:01"));
+    assert_eq!(info.brand.as_deref(), Some("Bluesound"));
+}
+
+#[tokio::test]
+async fn playback() {
+    let sim = SimulatedPlayer::start().await.unwrap();
+    let bluos = sim.client().unwrap();
+
+    assert_eq!(bluos.play().await.unwrap(), State::Play);
+    assert_eq!(bluos.pause(false).await.unwrap(), State::Pause);
+    assert_eq!(bluos.pause(true).await.unwrap(), State::Play);
+    assert_eq!(bluos.stop().await.unwrap(), State::Stop);
+
+    assert_eq!(
+        bluos.play_with_options(Some(30), None, None).await.unwrap(),
+        State::Play
+    );
+    assert_eq!(bluos.status().await.unwrap().seconds_played, Some(30));
+
+    assert_eq!(bluos.skip().await.unwrap().id, Some(1));
+    assert_eq!(bluos.skip().await.unwrap().id, Some(2));
+    assert_eq!(bluos.back().await.unwrap().id, Some(1));
+}
+
+#[tokio::test]
+async fn shuffle_and_repeat() {
+    let sim = SimulatedPlayer::start().await.unwrap();
+    let bluos = sim.client().unwrap();
+
+    bluos.shuffle(true).await.unwrap();
+    bluos.repeat(RepeatSetting::CurrentTrack).await.unwrap();
+
+    let status = bluos.status().await.unwrap();
+    assert_eq!(status.shuffle, 1);
+    assert_eq!(status.repeat, 1);
+}
+
+#[tokio::test]
+async fn volume() {
+    let sim = SimulatedPlayer::start().await.unwrap();
+    let bluos = sim.client().unwrap();
+
+    assert_eq!(bluos.volume().await.unwrap().volume, 25);
+    assert_eq!(bluos.set_volume(40, None).await.unwrap().volume, 40);
+    assert_eq!(
+        bluos.set_volume_db(-40.0, Some(true)).await.unwrap().volume,
+        50
+    );
+    assert_eq!(bluos.step_volume_db(-8.0, None).await.unwrap().volume, 40);
+
+    let muted = bluos.mute(None).await.unwrap();
+    assert_eq!(muted.mute, 1);
+    assert_eq!(muted.muted_volume, Some(40));
+
+    let unmuted = bluos.unmute(None).await.unwrap();
+    assert_eq!(unmuted.mute, 0);
+    assert_eq!(unmuted.volume, 40);
+}
+
+#[tokio::test]
+async fn queue() {
+    let sim = SimulatedPlayer::start().await.unwrap();
+    let bluos = sim.client().unwrap();
+
+    let queue = bluos.queue(None).await.unwrap();
+    assert_eq!(queue.length, 3);
+    assert_eq!(queue.entries.len(), 3);
+
+    let page = bluos.queue(Some(Pagination::new(1, 2))).await.unwrap();
+    assert_eq!(page.entries.len(), 2);
+    assert_eq!(page.entries[0].title.as_deref(), Some("Freddie Freeloader"));
+
+    bluos.queue_delete_song(0).await.unwrap();
+    let queue = bluos.queue(None).await.unwrap();
+    assert_eq!(queue.length, 2);
+    assert_eq!(queue.modified, 1);
+
+    bluos.queue_clear().await.unwrap();
+    let queue = bluos.queue(None).await.unwrap();
+    assert_eq!(queue.length, 0);
+    assert!(queue.entries.is_empty());
+}
+
+#[tokio::test]
+async fn browse_and_play() {
+    let sim = SimulatedPlayer::start().await.unwrap();
+    let bluos = sim.client().unwrap();
+
+    let root = bluos.browse(None).await.unwrap();
+    assert_eq!(root.items.len(), 2);
+
+    // The search key contains characters that have to be encoded
+    let search = &root.items[1];
+    let albums = bluos.browse(search.browse_key.as_deref()).await.unwrap();
+    assert_eq!(albums.items[0].text.as_deref(), Some("Kind of Blue"));
+
+    let tracks = bluos
+        .browse(albums.items[0].browse_key.as_deref())
+        .await
+        .unwrap();
+    assert_eq!(
+        bluos.play_item(&tracks.items[1]).await.unwrap(),
+        State::Stream
+    );
+    assert_eq!(
+        sim.model().stream_url.as_deref(),
+        Some("LocalMusic:track/3")
+    );
+
+    // A + in the path of an item is not a space
+    sim.update(|m| {
+        m.browse.insert(
+            "Radio".to_string(),
+            vec![SimBrowseItem::audio(
+                "Main Mix",
+                "/Play?url=Radio+Paradise%3Amain+mix",
+            )],
+        );
+    });
+    let radio = bluos.browse(Some("Radio")).await.unwrap();
+    bluos.play_item(&radio.items[0]).await.unwrap();
+    assert_eq!(
+        sim.model().stream_url.as_deref(),
+        Some("Radio+Paradise:main+mix")
+    );
+
+    assert!(matches!(
+        bluos.play_item(&root.items[0]).await,
+        Err(Error::NotPlayableError)
+    ));
+
+    assert_eq!(
+        bluos
+            .play_url("http://example.com/stream.mp3?a=1&b=2")
+            .await
+            .unwrap(),
+        State::Stream
+    );
+    assert_eq!(
+        sim.model().stream_url.as_deref(),
+        Some("http://example.com/stream.mp3?a=1&b=2")
+    );
+}
+
+#[tokio::test]
+async fn device_errors() {
+    let sim = SimulatedPlayer::start().await.unwrap();
+    let bluos = sim.client().unwrap();
+
+    match bluos.browse(Some("Nope:")).await {
+        Err(Error::DeviceError {
+            endpoint, message, ..
+        }) => {
+            assert_eq!(endpoint, "Browse");
+            assert_eq!(message, "Invalid browse key");
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn presets() {
+    let sim = SimulatedPlayer::start().await.unwrap();
+    let bluos = sim.client().unwrap();
+
+    let presets = bluos.presets().await.unwrap();
+    assert_eq!(presets.prid, 1);
+    assert_eq!(presets.entries.len(), 2);
+
+    bluos.load_preset(2).await.unwrap();
+    assert_eq!(sim.model().current_preset, Some(1));
+    bluos.next_preset().await.unwrap();
+    assert_eq!(sim.model().current_preset, Some(0));
+    bluos.previous_preset().await.unwrap();
+    assert_eq!(sim.model().current_preset, Some(1));
+
+    sim.update(|m| {
+        m.presets.pop();
+        m.prid += 1;
+    });
+
+    // Still cached until a status reports the new prid
+    assert_eq!(bluos.presets().await.unwrap().entries.len(), 2);
+    bluos.status().await.unwrap();
+    let presets = bluos.presets().await.unwrap();
+    assert_eq!(presets.prid, 2);
+    assert_eq!(presets.entries.len(), 1);
+}
+
+#[tokio::test]
+async fn grouping() {
+    let sim = SimulatedPlayer::start().await.unwrap();
+    let bluos = sim.client().unwrap();
+    let kitchen = SimulatedPlayer::start().await.unwrap();
+
+    let added = bluos
+        .add_slave(kitchen.addr(), Some("Downstairs"))
+        .await
+        .unwrap();
+    assert_eq!(added.len(), 1);
+
+    let added = bluos
+        .add_slaves(
+            vec![
+                PlayerAddress::new("10.0.0.2", 11000),
+                PlayerAddress::new("10.0.0.3", 11000),
+            ],
+            None,
+        )
+        .await
+        .unwrap();
+    assert_eq!(added.len(), 2);
+
+    let topology = GroupTopology::resolve(&[bluos]).await.unwrap();
+    assert_eq!(topology.groups[0].name.as_deref(), Some("Downstairs"));
+    assert_eq!(topology.groups[0].slaves.len(), 3);
+    assert!(topology.group_of(&kitchen.addr().into()).is_some());
+
+    let bluos = sim.client().unwrap();
+    let sync_status = bluos.remove_slave(kitchen.addr()).await.unwrap();
+    assert_eq!(sync_status.slaves.len(), 2);
+    assert_eq!(
+        bluos.status().await.unwrap().group_name.as_deref(),
+        Some("Downstairs")
+    );
+}
+
+#[tokio::test]
+async fn status_stream_long_polls() {
+    let sim = SimulatedPlayer::start().await.unwrap();
+    let bluos = sim.client().unwrap();
+    let controller = sim.client().unwrap();
+
+    let stream = bluos.status_stream();
+    futures::pin_mut!(stream);
+
+    let first = stream.next().await.unwrap().unwrap();
+    assert_eq!(first.state, "stop");
+
+    let (_, second) = tokio::join!(
+        async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            controller.play().await.unwrap();
+        },
+        stream.next()
+    );
+    let second = second.unwrap().unwrap();
+    assert_eq!(second.state, "play");
+    assert_ne!(first.etag, second.etag);
+}
+
+#[tokio::test]
+async fn short_long_poll_timeout() {
+    let sim = SimulatedPlayer::start().await.unwrap();
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let bluos = BluOS::builder()
+        .socket_addr(sim.addr())
+        .long_poll_timeout(Duration::from_millis(10))
+        .transport(RecordingTransport {
+            requests: requests.clone(),
+            ..Default::default()
+        })
+        .build()
+        .unwrap();
+
+    let stream = bluos.status_stream();
+    futures::pin_mut!(stream);
+    let first = stream.next().await.unwrap().unwrap();
+
+    // Rounding down to timeout=0 would make the player answer at once, over and over
+    let next = tokio::time::timeout(Duration::from_millis(1500), stream.next()).await;
+    assert!(next.is_err());
+    let requests = requests.lock().unwrap();
+    assert!(requests.len() <= 3, "{} requests", requests.len());
+    assert_eq!(
+        requests[1].path_and_query,
+        format!("/Status?timeout=1&etag={}", first.etag)
+    );
+}
+
+#[tokio::test]
+async fn status_events() {
+    let sim = SimulatedPlayer::start().await.unwrap();
+    let bluos = sim.client().unwrap();
+    let controller = sim.client().unwrap();
+
+    let events = bluos.status_events();
+    futures::pin_mut!(events);
+
+    let (_, event) = tokio::join!(
+        async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            controller.update_library().await.unwrap();
+        },
+        events.next()
+    );
+    assert_eq!(event.unwrap().unwrap(), StatusEvent::IndexingStarted);
+    assert_eq!(
+        events.next().await.unwrap().unwrap(),
+        StatusEvent::IndexingFinished
+    );
+}
+
+#[cfg(any(feature = "discover", feature = "discover-mdns-sd", feature = "lsdp"))]
+#[tokio::test]
+async fn device_registry() {
+    use bluos_api_rs::{
+        DeviceKind, DeviceRegistry, DiscoveredBluOSDevice, RegistryEvent, SimModel,
+    };
+    use futures::SinkExt;
+
+    fn announce(sim: &SimulatedPlayer) -> DiscoveredBluOSDevice {
+        DiscoveredBluOSDevice {
+            name: "Simulated Player".to_string(),
+            hostname: sim.addr().ip().to_string(),
+            port: sim.addr().port(),
+            addresses: vec![sim.addr().ip()],
+            interface: None,
+            service_type: "_musc._tcp".to_string(),
+            kind: DeviceKind::Player,
+            txt: Default::default(),
+        }
+    }
+
+    let sim = SimulatedPlayer::start().await.unwrap();
+    let (mut tx, rx) = futures::channel::mpsc::channel(10);
+    let mut registry = DeviceRegistry::with_check_interval(Duration::from_millis(50));
+    let mut events = registry.start_with(rx).unwrap();
+
+    // A player that accepts connections but never answers doesn't hold up the others
+    let silent = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let mut unresponsive = announce(&sim);
+    unresponsive.port = silent.local_addr().unwrap().port();
+    tx.send(Ok(unresponsive)).await.unwrap();
+
+    tx.send(Ok(announce(&sim))).await.unwrap();
+    let added = tokio::time::timeout(Duration::from_secs(1), events.next()).await;
+    match added.unwrap().unwrap() {
+        RegistryEvent::Added(player) => assert_eq!(player.mac, "02:00:00:00:00:01"),
+        other => panic!("unexpected {:?}", other),
+    }
+    let bluos = registry.player("02:00:00:00:00:01").unwrap();
+    assert_eq!(bluos.play().await.unwrap(), State::Play);
+
+    // The same player comes back on another address
+    let moved = SimulatedPlayer::bind("127.0.0.1:0".parse().unwrap(), SimModel::default())
+        .await
+        .unwrap();
+    drop(sim);
+    tx.send(Ok(announce(&moved))).await.unwrap();
+    match events.next().await.unwrap() {
+        RegistryEvent::AddressChanged { to, .. } => assert_eq!(to, moved.addr().into()),
+        other => panic!("unexpected {:?}", other),
+    }
+    assert!(registry.player_named("simulated player").is_some());
+
+    // And disappears
+    drop(moved);
+    match events.next().await.unwrap() {
+        RegistryEvent::Removed(player) => assert_eq!(player.mac, "02:00:00:00:00:01"),
+        other => panic!("unexpected {:?}", other),
+    }
+    assert!(registry.players().is_empty());
+}
+
+#[tokio::test]
+async fn request_timeout() {
+    // Accepts connections but never answers, like a player that dropped off the network
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(async move {
+        let mut connections = Vec::new();
+        while let Ok((socket, _)) = listener.accept().await {
+            connections.push(socket);
+        }
+    });
+
+    let bluos = BluOS::builder()
+        .socket_addr(addr)
+        .timeout(Duration::from_millis(100))
+        .retry_policy(RetryPolicy::none())
+        .user_agent("bluos-api-rs tests")
+        .build()
+        .unwrap();
+    match bluos.status().await {
+        Err(Error::RequestError(e)) => assert!(e.is_timeout()),
+        other => panic!("unexpected {:?}", other),
+    }
+    server.abort();
+}
+
+#[tokio::test]
+async fn retry_reads_only() {
+    let sim = SimulatedPlayer::start().await.unwrap();
+    let bluos = BluOS::builder()
+        .socket_addr(sim.addr())
+        .retry_policy(RetryPolicy {
+            initial_backoff: Duration::from_millis(10),
+            ..RetryPolicy::default()
+        })
+        .build()
+        .unwrap();
+
+    sim.update(|m| m.busy_responses = 2);
+    assert_eq!(bluos.status().await.unwrap().state, "stop");
+
+    // Skipping twice would skip a track too many, so the 503 is returned
+    sim.update(|m| m.busy_responses = 1);
+    match bluos.skip().await {
+        Err(Error::DeviceError { status, .. }) => assert_eq!(status, 503),
+        other => panic!("unexpected {:?}", other),
+    }
+    assert_eq!(sim.model().song, 0);
+
+    // The message the player sent along is kept once the retries run out
+    sim.update(|m| m.busy_responses = 3);
+    match bluos.status().await {
+        Err(Error::DeviceError {
+            status, message, ..
+        }) => {
+            assert_eq!(status, 503);
+            assert_eq!(message, "Service unavailable");
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn raw_commands() {
+    let sim = SimulatedPlayer::start().await.unwrap();
+    let bluos = sim.client().unwrap();
+
+    let body = bluos
+        .send_raw(RawCommand::new("Volume").param("level", 30))
+        .await
+        .unwrap();
+    assert!(body.contains("30"));
+
+    let sync_status: SyncStatus = bluos
+        .send_raw_xml(RawCommand::new("/SyncStatus").idempotent())
+        .await
+        .unwrap();
+    assert_eq!(sync_status.volume, 30);
+
+    assert!(matches!(
+        bluos.send_raw(RawCommand::new("Undocumented")).await,
+        Err(Error::DeviceError { .. })
+    ));
+}
+
+/// Records the requests and fails the first `failures` of them before passing them on
+#[derive(Debug, Default)]
+struct RecordingTransport {
+    inner: ReqwestTransport,
+    requests: Arc<Mutex<Vec<TransportRequest>>>,
+    failures: Mutex<u32>,
+}
+
+impl Transport for RecordingTransport {
+    fn get(&self, request: TransportRequest) -> BoxFuture<'_, Result<TransportResponse, Error>> {
+        self.requests.lock().unwrap().push(request.clone());
+        let mut failures = self.failures.lock().unwrap();
+        if *failures > 0 {
+            *failures -= 1;
+            return Box::pin(async { Err(Error::TransportError("gateway unreachable".into())) });
+        }
+        self.inner.get(request)
+    }
+}
+
+#[tokio::test]
+async fn custom_transport() {
+    let sim = SimulatedPlayer::start().await.unwrap();
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let bluos = BluOS::builder()
+        .socket_addr(sim.addr())
+        .user_agent("bluos-test")
+        .retry_policy(RetryPolicy {
+            initial_backoff: Duration::from_millis(1),
+            ..RetryPolicy::default()
+        })
+        .transport(RecordingTransport {
+            requests: requests.clone(),
+            failures: Mutex::new(1),
+            ..Default::default()
+        })
+        .build()
+        .unwrap();
+
+    // The failed attempt is retried
+    assert_eq!(bluos.status().await.unwrap().volume, 25);
+    bluos.set_volume(30, Some(true)).await.unwrap();
+    assert_eq!(bluos.set_volume(150, None).await.unwrap().volume, 100);
+
+    let requests = requests.lock().unwrap();
+    assert_eq!(requests.len(), 4);
+    assert_eq!(requests[0], requests[1]);
+    assert_eq!(requests[1].path_and_query, "/Status");
+    assert_eq!(requests[2].path_and_query, "/Volume?level=30&tell_slaves=1");
+    assert_eq!(requests[2].host, sim.addr().ip().to_string());
+    assert_eq!(requests[2].user_agent.as_deref(), Some("bluos-test"));
+    assert_eq!(requests[3].path_and_query, "/Volume?level=100");
+}
+
+#[cfg(feature = "cassette")]
+#[tokio::test]
+async fn cassette_record_and_replay() {
+    use bluos_api_rs::CassetteTransport;
+
+    let path = std::env::temp_dir().join(format!("bluos-{}.cassette", std::process::id()));
+    let sim = SimulatedPlayer::start().await.unwrap();
+    let recorder = BluOS::builder()
+        .socket_addr(sim.addr())
+        .transport(CassetteTransport::record(&path, ReqwestTransport::default()).unwrap())
+        .build()
+        .unwrap();
+
+    let status = recorder.status().await.unwrap();
+    let queue = recorder.queue(None).await.unwrap();
+    let root = recorder.browse(None).await.unwrap();
+    let albums = recorder
+        .browse(root.items[1].browse_key.as_deref())
+        .await
+        .unwrap();
+    drop(sim);
+
+    // The player is gone, everything comes from the recording
+    let replayer = BluOS::builder()
+        .hostname("recorded.invalid")
+        .transport(CassetteTransport::replay(&path).unwrap())
+        .build()
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(replayer.status().await.unwrap(), status);
+    assert_eq!(replayer.queue(None).await.unwrap(), queue);
+    assert_eq!(replayer.browse(None).await.unwrap(), root);
+    assert_eq!(
+        replayer
+            .browse(root.items[1].browse_key.as_deref())
+            .await
+            .unwrap(),
+        albums
+    );
+
+    // Every recorded response is only replayed once
+    assert!(matches!(
+        replayer.status().await,
+        Err(Error::CassetteError(_))
+    ));
+}